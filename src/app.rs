@@ -1,26 +1,92 @@
-use macroquad::{prelude::*, rand::RandGenerator};
-use std::time::{SystemTime, UNIX_EPOCH};
+use macroquad::{prelude::*, rand::RandGenerator, ui::root_ui};
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    CHUNK_SIZE, RENDER_SIZE,
-    brush::Brush,
+    brush::{Brush, BrushMode, BrushType},
+    camera::{ScalingMode, WorldCamera},
+    command::AppCommand,
+    config_path,
+    console::{COMMANDS, Console, ConsoleCommand, parse},
+    history::Timeline,
+    image_io::{Palette, export_png, import_png},
+    keybindings::{Action, Keybindings},
     pixel::PixelType,
-    pixel_grid::{Chunk, ChunkGrid},
+    pixel_grid::ChunkGrid,
+    plugin::PluginHost,
+    prefab::PrefabLibrary,
+    recorder::FrameRecorder,
+    replay::{Replay, ReplayPlayer},
+    rules::RuleSet,
+    save::{load_world, save_world},
+    scenario::{Scenario, ScenarioRun},
+    scripting::MaterialScripts,
+    selection::Pattern,
+    settings::Settings,
 };
-pub struct App {
-    render_ratio: (f32, f32),
 
+// How fast the keyboard pans the camera, in screen pixels of the render target per second
+const PAN_SPEED: f32 = 150.0;
+// How much one scroll step zooms in or out
+const ZOOM_STEP: f32 = 1.25;
+
+/// The mouse button that is currently held down for painting or erasing
+#[derive(Clone, Copy)]
+struct Stroke {
+    // Where the button was pressed, the start of a line or rectangle
+    start: Vec2,
+    // Where the mouse was last frame, so stamping brushes can fill in the gap
+    last: Vec2,
+    erase: bool,
+    // This press placed the clipboard or used a plugin brush, so holding and releasing it shouldn't paint
+    placed: bool,
+}
+
+pub struct App {
     chunk_grid: ChunkGrid,
     render_target: RenderTarget,
     render_camera: Camera2D,
     default_camera: Camera2D,
+    camera: WorldCamera,
+    last_mouse_position: Vec2,
+    // Where the render target is drawn on screen
+    screen_rect: Rect,
+    // The window size, scaling mode and render size the render target was last laid out for
+    layout: (Vec2, ScalingMode, (u32, u32)),
 
     should_quit: bool,
+    paused: bool,
     total_scroll: f32,
     brush: Brush,
+    stroke: Option<Stroke>,
+    // The two corners of the selected rectangle, in world pixels
+    selection: Option<(Vec2, Vec2)>,
+    selecting: bool,
+    clipboard: Option<Pattern>,
+    // While pasting, clicking places the clipboard instead of painting
+    pasting: bool,
+    // The plugin brush tool clicking uses instead of the brush, if one is picked
+    plugin_brush: Option<String>,
+    prefabs: PrefabLibrary,
+    keybindings: Keybindings,
+    keybindings_path: PathBuf,
+    settings: Settings,
+
+    timeline: Timeline,
+    recording: Option<Replay>,
+    playback: Option<ReplayPlayer>,
+    capture: Option<FrameRecorder>,
+    // The cell the inspector is watching, instead of the one under the mouse
+    pinned_cell: Option<Vec2>,
+    console: Console,
+    scenario: Option<ScenarioRun>,
 }
 impl App {
-    pub fn new(render_ratio: (f32, f32)) -> Self {
+    pub fn new(settings: Settings) -> Self {
+        // Handle closing the window ourselves, so we get the chance to save the settings
+        prevent_quit();
         // Create a seed and RNG
         let rng = RandGenerator::new();
         let mut seed = SystemTime::now()
@@ -29,170 +95,984 @@ impl App {
             .as_nanos()
             .try_into()
             .expect("Time went too fast");
-        seed = seed % 12345678;
+        seed %= 12345678;
         rng.srand(seed);
         println!("Started app with seed: {seed}");
         // Create pixelgrid with the seed
-        let chunk_grid = ChunkGrid::new(seed.try_into().unwrap(), rng);
+        let mut chunk_grid = ChunkGrid::new(seed, rng);
+        chunk_grid.set_scripts(MaterialScripts::load(&config_path("scripts")));
+        chunk_grid.set_plugins(PluginHost::load(&config_path("plugins")));
+        chunk_grid.set_rules(RuleSet::load(&config_path("rules")));
         // Create the texture to which we will draw
-        let render_target = render_target(RENDER_SIZE.0, RENDER_SIZE.1);
-        // Set filter mode to nearest to prevent blurry pixels
-        render_target.texture.set_filter(FilterMode::Nearest);
+        // It gets its real size in update_layout(), once we know how the window is laid out
+        let render_size = settings.render_size;
+        let render_target = render_target(render_size.0, render_size.1);
         // Create the camera which we use to render. The render target is attached to this camera
-        let mut render_camera = Camera2D::from_display_rect(Rect {
-            x: 0.0,
-            y: 0.0,
-            w: RENDER_SIZE.0 as f32, // this camera's viewport has the render dimensions
-            h: RENDER_SIZE.1 as f32,
-        });
-        // Attach render target to this camera
-        render_camera.render_target = Some(render_target.clone());
-        // Create camera which we use to draw the final texture.
+        // At zoom 1 this camera's viewport has the render dimensions, and it can be moved around the world
+        let camera = WorldCamera::new(vec2(render_size.0 as f32, render_size.1 as f32));
+        let render_camera = camera.to_camera2d(&render_target);
+        // The camera which we use to draw the final texture.
         // This camera is essentially our screen, whereas the render_camera is the viewport
         // The render_camera is then scaled to our screen dimensions during drawing
-        let default_camera = Camera2D::from_display_rect(Rect {
-            x: 0.0,
-            y: 0.0,
-            w: screen_width(), // this camera's viewport has the screen dimensions
-            h: screen_height(),
-        });
-        Self {
-            render_ratio,
-
+        let default_camera = Camera2D::default();
+        let timeline = Timeline::new(
+            settings.snapshot_capacity.max(1) as usize,
+            settings.snapshot_interval.max(1) as u64,
+        );
+        let mut app = Self {
             chunk_grid,
             render_target,
             render_camera,
             default_camera,
+            camera,
+            last_mouse_position: mouse_position().into(),
+            screen_rect: Rect::default(),
+            layout: (Vec2::ZERO, settings.scaling_mode, (0, 0)),
 
             should_quit: false,
+            paused: false,
             total_scroll: 0.0,
 
-            brush: Brush::new(),
-        }
+            brush: Brush::new(
+                settings.default_material,
+                settings.default_brush,
+                settings.default_brush_size,
+                settings.default_spray_density,
+            ),
+            stroke: None,
+            selection: None,
+            selecting: false,
+            clipboard: None,
+            pasting: false,
+            plugin_brush: None,
+            prefabs: PrefabLibrary::load(config_path("prefabs")),
+            keybindings: Keybindings::defaults(),
+            keybindings_path: config_path("keybindings.ron"),
+            settings,
+
+            timeline,
+            recording: None,
+            playback: None,
+            capture: None,
+            pinned_cell: None,
+            console: Console::default(),
+            scenario: None,
+        };
+        app.reload_keybindings();
+        app.update_layout();
+        app
     }
 
     pub fn running(&self) -> bool {
         !self.should_quit
     }
-    fn quit(&mut self) {
-        self.should_quit = true;
-    }
 
     pub fn brush(&self) -> Brush {
         self.brush
     }
-    pub fn brush_mut(&mut self) -> &mut Brush {
-        &mut self.brush
+
+    /// The cell the inspector shows: the pinned one, or the one under the mouse
+    pub fn inspected_cell(&self) -> Vec2 {
+        self.pinned_cell.unwrap_or_else(|| self.mouse_to_world())
+    }
+
+    pub fn is_inspector_pinned(&self) -> bool {
+        self.pinned_cell.is_some()
+    }
+
+    pub fn keybindings(&self) -> &Keybindings {
+        &self.keybindings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    /// Writes the settings to the config directory, with the window size we are closing at,
+    /// and finishes a capture that is still running
+    pub fn save_settings(&mut self) {
+        self.stop_capture();
+        self.settings.window_width = screen_width() as u32;
+        self.settings.window_height = screen_height() as u32;
+        let path = config_path("settings.ron");
+        if let Err(error) = self.settings.save(&path) {
+            println!("Failed to save settings to {}: {error}", path.display());
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    pub fn playback(&self) -> Option<&ReplayPlayer> {
+        self.playback.as_ref()
+    }
+
+    pub fn capture(&self) -> Option<&FrameRecorder> {
+        self.capture.as_ref()
+    }
+
+    /// Applies a command to the app. Every change to the world goes through here,
+    /// no matter if it came from the keyboard, the mouse, the ui or the console
+    pub fn execute(&mut self, command: AppCommand) {
+        // While a replay is playing, the replay is the only thing allowed to touch the world
+        if self.is_playing() && command.is_recorded() {
+            // Strokes come in every frame the mouse is held, so don't complain about those
+            if !matches!(command, AppCommand::Paint { .. } | AppCommand::Erase { .. }) {
+                println!("A replay is playing, stop it before changing the world");
+            }
+            return;
+        }
+        // A loaded world comes from a file the replay doesn't carry, so it can't be replayed
+        if matches!(command, AppCommand::Load(_)) && (self.is_recording() || self.is_playing()) {
+            println!("Stop recording or playing back before loading a world");
+            return;
+        }
+        self.apply(command);
     }
 
-    pub fn reset(&mut self) {
-        self.chunks_mut().clear();
+    /// Applies a command without checking who sent it. Only the replay player
+    /// calls this directly, for the commands it feeds back in
+    fn apply(&mut self, command: AppCommand) {
+        if command.is_recorded()
+            && let Some(replay) = self.recording.as_mut()
+        {
+            replay.push(self.chunk_grid.tick(), command.clone());
+        }
+
+        match command {
+            AppCommand::Paint { from, to } => {
+                // Painting into an older snapshot makes it the new present
+                self.timeline.branch();
+                self.brush
+                    .stroke(from.into(), to.into(), &mut self.chunk_grid);
+            }
+            AppCommand::Erase { from, to } => {
+                self.timeline.branch();
+                let mut eraser = self.brush;
+                *eraser.pixel_type_mut() = PixelType::Air;
+                // Erasing only into empty pixels would do nothing, so the eraser ignores that mode
+                if eraser.brush_mode() == BrushMode::EmptyOnly {
+                    eraser.set_brush_mode(BrushMode::Normal);
+                }
+                eraser.stroke(from.into(), to.into(), &mut self.chunk_grid);
+            }
+            AppCommand::Clear => {
+                self.timeline.branch();
+                self.chunk_grid.clear();
+            }
+            AppCommand::Fill { from, to, material } => {
+                self.timeline.branch();
+                // Only the loaded chunks can be filled, so keep a huge rectangle from
+                // visiting millions of positions that don't exist
+                if let Some((min, max)) = self.chunk_grid.bounds() {
+                    Brush::new(material, BrushType::Rectangle, 1.0, 0.0).stroke(
+                        Vec2::from(from).clamp(min, max),
+                        Vec2::from(to).clamp(min, max),
+                        &mut self.chunk_grid,
+                    );
+                }
+            }
+            AppCommand::ClearChunk(x, y) => {
+                self.timeline.branch();
+                if !self.chunk_grid.clear_chunk((x, y)) {
+                    println!("There is no chunk at {x}, {y}");
+                }
+            }
+            AppCommand::PlacePattern { pattern, x, y } => {
+                self.timeline.branch();
+                pattern.place(vec2(x, y), &mut self.chunk_grid);
+            }
+            AppCommand::Restart(seed) => {
+                // A replay can't follow the world being swapped out under it
+                if self.is_recording() || self.is_playing() {
+                    println!("Stop recording or playing a replay before restarting");
+                    return;
+                }
+                self.restart(seed);
+                println!("Restarted with seed {seed}");
+            }
+            AppCommand::RunScenario(path) => {
+                if self.is_recording() || self.is_playing() {
+                    println!("Stop recording or playing a replay before running a scenario");
+                    return;
+                }
+                let scenario = match Scenario::load(&path) {
+                    Ok(scenario) => scenario,
+                    Err(error) => {
+                        println!("Failed to load scenario from {path}: {error}");
+                        return;
+                    }
+                };
+                self.restart(scenario.seed());
+                match ScenarioRun::start(scenario, &mut self.chunk_grid) {
+                    Ok(run) => {
+                        println!("Running scenario {path}");
+                        self.scenario = Some(run);
+                    }
+                    Err(error) => println!("Failed to set up scenario {path}: {error}"),
+                }
+            }
+            AppCommand::StopScenario => self.scenario = None,
+            AppCommand::Save(path) => match save_world(&self.chunk_grid, &path) {
+                Ok(()) => println!("Saved world to {path}"),
+                Err(error) => println!("Failed to save world to {path}: {error}"),
+            },
+            AppCommand::Load(path) => {
+                self.timeline.branch();
+                match load_world(&mut self.chunk_grid, &path) {
+                    Ok(()) => println!("Loaded world from {path}"),
+                    Err(error) => println!("Failed to load world from {path}: {error}"),
+                }
+            }
+
+            AppCommand::SelectMaterial(pixel_type) => *self.brush.pixel_type_mut() = pixel_type,
+            AppCommand::NextMaterial => self.brush.pixel_type_mut().next(),
+            AppCommand::PreviousMaterial => self.brush.pixel_type_mut().previous(),
+            AppCommand::SelectBrushType(brush_type) => *self.brush.brush_type_mut() = brush_type,
+            AppCommand::NextBrushType => self.brush.brush_type_mut().next(),
+            AppCommand::PreviousBrushType => self.brush.brush_type_mut().previous(),
+            AppCommand::SetBrushSize(size) => self.brush.set_size(size),
+            AppCommand::GrowBrush(amount) => self.brush.increase_size(amount),
+            AppCommand::ShrinkBrush(amount) => self.brush.decrease_size(amount),
+            AppCommand::SetSprayDensity(density) => self.brush.set_spray_density(density),
+            AppCommand::SetBrushMode(brush_mode) => self.brush.set_brush_mode(brush_mode),
+            AppCommand::Copy { from, to } => {
+                self.clipboard = Some(Pattern::capture(&self.chunk_grid, from.into(), to.into()));
+            }
+            AppCommand::Cut { from, to } => {
+                self.timeline.branch();
+                self.clipboard = Some(Pattern::capture(&self.chunk_grid, from.into(), to.into()));
+                // Clear the selection by drawing an Air rectangle over it
+                Brush::new(PixelType::Air, BrushType::Rectangle, 1.0, 0.0).stroke(
+                    from.into(),
+                    to.into(),
+                    &mut self.chunk_grid,
+                );
+            }
+            AppCommand::Paste { x, y } => {
+                if let Some(clipboard) = &self.clipboard {
+                    self.timeline.branch();
+                    clipboard.place(clipboard.top_left_at(vec2(x, y)), &mut self.chunk_grid);
+                }
+            }
+            AppCommand::TogglePasting => {
+                self.pasting = !self.pasting && self.clipboard.is_some();
+            }
+            AppCommand::SetClipboard(clipboard) => {
+                self.clipboard = clipboard;
+                self.pasting &= self.clipboard.is_some();
+            }
+            AppCommand::SelectPluginBrush(name) => self.plugin_brush = name,
+            AppCommand::UsePluginBrush { name, x, y } => {
+                let random = self.chunk_grid.random_range(0, i32::MAX);
+                let size = self.brush.size() as i32;
+                match self.chunk_grid.plugins().brush(&name, size, random) {
+                    Ok(cells) => {
+                        self.timeline.branch();
+                        for ((dx, dy), pixel_type) in cells {
+                            let position = vec2(x + dx as f32, y + dy as f32);
+                            // The plugin brush follows the brush mode, like the built-in brushes
+                            if let Some(current) = self.chunk_grid.get_pixel(position)
+                                && self.brush.brush_mode().allows(current)
+                            {
+                                self.chunk_grid.set_pixel(position, pixel_type);
+                            }
+                        }
+                    }
+                    Err(error) => println!("Failed to use plugin brush {name}: {error}"),
+                }
+            }
+            AppCommand::SavePrefab(name) => {
+                let pattern = match self.selection {
+                    Some((from, to)) => Pattern::capture(&self.chunk_grid, from, to),
+                    None => match &self.clipboard {
+                        Some(clipboard) => clipboard.clone(),
+                        None => {
+                            println!("Select something before saving it as a prefab");
+                            return;
+                        }
+                    },
+                };
+                match self.prefabs.save(&name, &pattern) {
+                    Ok(()) => println!("Saved prefab {name}"),
+                    Err(error) => println!("Failed to save prefab {name}: {error}"),
+                }
+            }
+            AppCommand::PlacePrefab(name) => match self.prefabs.get(&name) {
+                Some(prefab) => {
+                    // Goes through execute, so a recording knows what is being pasted
+                    self.execute(AppCommand::SetClipboard(Some(prefab.pattern().clone())));
+                    self.pasting = true;
+                }
+                None => println!("There is no prefab called {name}"),
+            },
+            AppCommand::ReloadPrefabs => self.prefabs.reload(),
+            AppCommand::ImportImage(path) => {
+                // Load the palette every time, so changes to it are picked up right away
+                let palette = Palette::load_or_create(&config_path("palette.ron"));
+                match import_png(&path, &palette) {
+                    Ok(pattern) => {
+                        println!("Imported {path}, click to place it");
+                        self.execute(AppCommand::SetClipboard(Some(pattern)));
+                        self.pasting = true;
+                    }
+                    Err(error) => println!("Failed to import {path}: {error}"),
+                }
+            }
+            AppCommand::ExportImage { path, scale } => {
+                let Some((from, to)) = self.selection.or(self.chunk_grid.bounds()) else {
+                    return;
+                };
+                match export_png(&self.chunk_grid, from, to, scale, &path) {
+                    Ok(()) => println!("Exported to {path}"),
+                    Err(error) => println!("Failed to export to {path}: {error}"),
+                }
+            }
+            AppCommand::RotateClipboard => {
+                if let Some(clipboard) = self.clipboard.as_mut() {
+                    clipboard.rotate();
+                }
+            }
+            AppCommand::FlipClipboard { vertical } => {
+                if let Some(clipboard) = self.clipboard.as_mut() {
+                    clipboard.flip(vertical);
+                }
+            }
+
+            AppCommand::Pause(paused) => self.paused = paused,
+            AppCommand::TogglePause => self.paused = !self.paused,
+            AppCommand::Step => {
+                self.timeline.branch();
+                self.tick();
+            }
+            AppCommand::Steps(ticks) => {
+                self.timeline.branch();
+                for _ in 0..ticks {
+                    self.tick();
+                }
+            }
+
+            AppCommand::PanCamera { dx, dy } => self.camera.pan(vec2(dx, dy)),
+            AppCommand::DragCamera { dx, dy } => self.camera.drag(vec2(dx, dy)),
+            AppCommand::ZoomCamera { factor, x, y } => self.camera.zoom_at(factor, vec2(x, y)),
+
+            AppCommand::Scrub(index) => self.scrub(index),
+            AppCommand::BackToLive => {
+                if let Some(live) = self.timeline.back_to_live() {
+                    self.chunk_grid.restore(&live);
+                }
+            }
+            AppCommand::Branch => self.timeline.branch(),
+
+            AppCommand::StartRecording => {
+                // The scenario's actions aren't commands, so a replay would miss them
+                if self.is_running_scenario() {
+                    println!("Stop the scenario before recording a replay");
+                    return;
+                }
+                self.start_recording();
+            }
+            AppCommand::StopRecording(path) => self.stop_recording(&path),
+            AppCommand::PlayReplay(path) => {
+                if self.is_running_scenario() {
+                    println!("Stop the scenario before playing a replay");
+                    return;
+                }
+                self.play_replay(&path);
+            }
+            AppCommand::StopPlayback => self.playback = None,
+            AppCommand::StartCapture {
+                path,
+                format,
+                every,
+                scale,
+                fps,
+            } => {
+                self.stop_capture();
+                let view = self.camera.view_rect();
+                let region = self.selection.unwrap_or((
+                    view.point().floor(),
+                    (view.point() + view.size()).floor() - 1.0,
+                ));
+                match FrameRecorder::start(format, &path, region, every, scale, fps) {
+                    Ok(recorder) => self.capture = Some(recorder),
+                    Err(error) => println!("Failed to start capturing to {path}: {error}"),
+                }
+            }
+            AppCommand::StopCapture => self.stop_capture(),
+            AppCommand::PinInspector(position) => self.pinned_cell = position.map(Vec2::from),
+
+            AppCommand::ReloadScripts => {
+                let folder = config_path("scripts");
+                self.chunk_grid.set_scripts(MaterialScripts::load(&folder));
+                println!("Reloaded scripts from {}", folder.display());
+            }
+            AppCommand::ReloadPlugins => {
+                let folder = config_path("plugins");
+                self.chunk_grid.set_plugins(PluginHost::load(&folder));
+                // The picked brush might be gone now
+                self.plugin_brush = self.plugin_brush.take().filter(|name| {
+                    self.chunk_grid
+                        .plugins()
+                        .brushes()
+                        .any(|brush| brush == name)
+                });
+                println!("Reloaded plugins from {}", folder.display());
+            }
+            AppCommand::ReloadRules => {
+                let folder = config_path("rules");
+                self.chunk_grid.set_rules(RuleSet::load(&folder));
+                println!("Reloaded rules from {}", folder.display());
+            }
+            AppCommand::ReloadKeybindings => {
+                self.reload_keybindings();
+                println!(
+                    "Reloaded keybindings from {}",
+                    self.keybindings_path.display()
+                );
+            }
+            AppCommand::Quit => self.should_quit = true,
+        }
+    }
+
+    /// Advances the simulation by one tick, unless paused or looking at an older snapshot
+    pub fn update(&mut self) {
+        // Feed the replay commands for this tick before simulating it,
+        // the same moment they were executed while recording
+        if let Some(playback) = self.playback.as_mut() {
+            let commands = playback.due_commands(self.chunk_grid.tick());
+            let finished = playback.is_finished();
+            for command in commands {
+                self.apply(command);
+            }
+            if finished {
+                println!("Replay finished at tick {}", self.chunk_grid.tick());
+                self.playback = None;
+            }
+        }
+
+        if self.paused || self.timeline.is_scrubbing() {
+            return;
+        }
+        self.tick();
+    }
+
+    fn tick(&mut self) {
+        self.chunk_grid.update();
+        // Before the snapshot, so the scenario's actions end up in it
+        if let Some(scenario) = self.scenario.as_mut() {
+            scenario.update(&mut self.chunk_grid);
+        }
+        self.timeline.record(self.chunk_grid.snapshot());
+        if let Some(capture) = self.capture.as_mut()
+            && let Err(error) = capture.capture(&self.chunk_grid)
+        {
+            println!("Failed to capture a frame: {error}");
+            self.stop_capture();
+        }
+    }
+
+    /// Stops capturing frames and finishes the file
+    fn stop_capture(&mut self) {
+        if let Some(capture) = self.capture.take() {
+            let frames = capture.frames();
+            match capture.finish() {
+                Ok(path) => println!("Captured {frames} frames to {path}"),
+                Err(error) => println!("Failed to finish the capture: {error}"),
+            }
+        }
+    }
+
+    /// Shows the snapshot at `index` in the timeline. The simulation stays frozen
+    /// until we either go back to live or branch off from this snapshot
+    fn scrub(&mut self, index: usize) {
+        // Jumping back in time would mess up the ticks of a replay or scenario
+        if self.is_recording() || self.is_playing() || self.is_running_scenario() {
+            return;
+        }
+        let chunk_grid = &mut self.chunk_grid;
+        if let Some(snapshot) = self.timeline.scrub(index, || chunk_grid.snapshot()) {
+            chunk_grid.restore(snapshot);
+        }
+    }
+
+    /// Restarts the world and starts recording every command into a replay
+    fn start_recording(&mut self) {
+        self.playback = None;
+        self.restart(self.chunk_grid.seed());
+        let mut replay = Replay::new(self.chunk_grid.seed());
+        // Store the brush and clipboard we start with, so playback doesn't depend on what is selected at the time
+        replay.push(0, AppCommand::SelectMaterial(self.brush.pixel_type()));
+        replay.push(0, AppCommand::SelectBrushType(self.brush.brush_type()));
+        replay.push(0, AppCommand::SetBrushSize(self.brush.size()));
+        replay.push(0, AppCommand::SetSprayDensity(self.brush.spray_density()));
+        replay.push(0, AppCommand::SetBrushMode(self.brush.brush_mode()));
+        replay.push(0, AppCommand::SetClipboard(self.clipboard.clone()));
+        self.recording = Some(replay);
+    }
+
+    /// Stops recording and writes the replay to `path`
+    fn stop_recording(&mut self, path: &str) {
+        if let Some(replay) = self.recording.take() {
+            match replay.save(path) {
+                Ok(()) => println!("Saved replay with {} commands to {path}", replay.len()),
+                Err(error) => println!("Failed to save replay to {path}: {error}"),
+            }
+        }
+    }
+
+    /// Loads a replay from `path` and plays it back from a fresh world
+    fn play_replay(&mut self, path: &str) {
+        let replay = match Replay::load(path) {
+            Ok(replay) => replay,
+            Err(error) => {
+                println!("Failed to load replay from {path}: {error}");
+                return;
+            }
+        };
+        self.recording = None;
+        self.restart(replay.seed());
+        self.playback = Some(ReplayPlayer::new(replay));
+    }
+
+    /// Whether a scenario is still doing things to the world
+    fn is_running_scenario(&self) -> bool {
+        self.scenario.as_ref().is_some_and(|run| !run.is_finished())
+    }
+
+    /// Clears the world, the timeline and the tick counter and starts over with `seed`
+    fn restart(&mut self, seed: u64) {
+        self.timeline.clear();
+        self.chunk_grid.restart(seed);
+        self.paused = false;
+    }
+
+    pub fn camera(&self) -> &WorldCamera {
+        &self.camera
     }
 
     pub fn mouse_to_world(&self) -> Vec2 {
-        let m_screen_pos = mouse_position(); // Get mouse position
-        let m_world_pos = self
-            .render_camera
-            .screen_to_world(vec2(m_screen_pos.0, m_screen_pos.1)) // Transform mouse position to world space
-            .round(); // Round world position to integer, to prevent pixels at half positions
-        return m_world_pos;
+        let m_screen_pos: Vec2 = mouse_position().into(); // Get mouse position
+        // Find where the mouse is on the render target, from 0 to 1, and from there where it is in the view
+        let on_target = (m_screen_pos - self.screen_rect.point()) / self.screen_rect.size();
+        let view = self.camera.view_rect();
+        (view.point() + on_target * view.size()).floor() // Round world position down to integer, to prevent pixels at half positions
     }
-    fn handle_mouse_input(&mut self) {
-        if is_mouse_button_down(MouseButton::Left) {
-            let world_position = self.mouse_to_world();
-            self.brush().draw(world_position, self.chunks_mut());
+
+    /// Resizes the render target and the cameras when the window size, scaling mode or render size changed
+    fn update_layout(&mut self) {
+        let screen_size = vec2(screen_width(), screen_height());
+        let layout = (
+            screen_size,
+            self.settings.scaling_mode,
+            self.settings.render_size,
+        );
+        if layout == self.layout {
+            return;
         }
+        self.layout = layout;
 
-        // Handle scrolling
+        let render_size = vec2(
+            self.settings.render_size.0.max(1) as f32,
+            self.settings.render_size.1.max(1) as f32,
+        );
+        let (target_size, screen_rect) =
+            self.settings.scaling_mode.layout(screen_size, render_size);
+        self.screen_rect = screen_rect;
+        if target_size != self.render_target.texture.size() {
+            self.render_target = render_target(target_size.x as u32, target_size.y as u32);
+        }
+        // Set filter mode to nearest to prevent blurry pixels
+        self.render_target.texture.set_filter(FilterMode::Nearest);
+        self.camera.set_view_size(target_size);
+        self.default_camera = Camera2D::from_display_rect(Rect {
+            x: 0.0,
+            y: 0.0,
+            w: screen_size.x, // this camera's viewport has the screen dimensions
+            h: screen_size.y,
+        });
+    }
+    /// Turns the wheel movement of this frame into whole scroll steps up and down
+    fn scroll_steps(&mut self) -> (u32, u32) {
         // First we get the vertical scroll direction and the amount that is scrolled
-        let mut scroll = mouse_wheel().1;
+        let scroll = mouse_wheel().1;
         // First we check if we are scrolling up
         if scroll > 0.0 {
             self.total_scroll += scroll; // Add the total amount scrolled
-            // Once we scrolled 120.0 up (idk in what unit) we count it as '1 scroll'
-            if self.total_scroll >= 120.0 {
-                // We divide the total scroll by 120.0 to get the total scroll amount in single units
-                scroll = self.total_scroll / 120.0;
-                // We loop over how many times we have scrolled and do an action for every scroll
-                for _ in 0..scroll as i32 {
-                    if is_key_down(KeyCode::LeftShift) {
-                        self.brush_mut().brush_type_mut().next();
-                        continue;
-                    }
-
-                    if is_key_down(KeyCode::LeftAlt) {
-                        self.brush_mut().increase_size(1.0);
-                        continue;
-                    }
-                    self.brush_mut().pixel_type_mut().next();
-                }
+            // Once we scrolled past the threshold (120.0 by default, idk in what unit) we count it as '1 scroll'
+            let threshold = self.settings.scroll_up_threshold;
+            if self.total_scroll >= threshold {
+                // We divide the total scroll by the threshold to get the total scroll amount in single units
+                let steps = (self.total_scroll / threshold) as u32;
                 self.total_scroll = 0.0;
+                return (steps, 0);
             }
             // Then we do that exact same thing but for scrolling down
         } else if scroll < 0.0 {
             self.total_scroll += scroll;
-            if self.total_scroll <= -110.0 {
+            let threshold = self.settings.scroll_down_threshold;
+            if self.total_scroll <= threshold {
                 // scrolled down
-                scroll = self.total_scroll / 120.0;
-                for _ in 0..scroll.abs() as i32 {
-                    if is_key_down(KeyCode::LeftShift) {
-                        self.brush_mut().brush_type_mut().previous();
-                        continue;
-                    }
+                let steps = (self.total_scroll / threshold).abs() as u32;
+                self.total_scroll = 0.0;
+                return (0, steps);
+            }
+        }
+        (0, 0)
+    }
 
-                    if is_key_down(KeyCode::LeftAlt) {
-                        self.brush_mut().decrease_size(1.0);
-                        continue;
-                    }
-                    self.brush_mut().pixel_type_mut().previous();
+    /// Turns holding down the paint or erase button into strokes. `held` is None when neither is held,
+    /// otherwise it says whether we are erasing
+    fn stroke_command(&mut self, held: Option<bool>) -> Option<AppCommand> {
+        let position = self.mouse_to_world();
+        let brush_type = self.brush.brush_type();
+        let (from, to, erase) = match (held, self.stroke.as_mut()) {
+            // Just pressed. Lines and rectangles wait for the release, the rest draws right away
+            (Some(erase), None) => {
+                let paste = self.pasting && !erase;
+                let plugin_brush = self.plugin_brush.clone().filter(|_| !paste && !erase);
+                self.stroke = Some(Stroke {
+                    start: position,
+                    last: position,
+                    erase,
+                    placed: paste || plugin_brush.is_some(),
+                });
+                if paste {
+                    return Some(AppCommand::Paste {
+                        x: position.x,
+                        y: position.y,
+                    });
+                }
+                if let Some(name) = plugin_brush {
+                    return Some(AppCommand::UsePluginBrush {
+                        name,
+                        x: position.x,
+                        y: position.y,
+                    });
+                }
+                if brush_type.is_drag_shape() {
+                    return None;
+                }
+                (position, position, erase)
+            }
+            // Still held. Stamping brushes continue from where the mouse was last frame,
+            // fill only fires once per press
+            (Some(_), Some(stroke)) => {
+                let from = stroke.last;
+                stroke.last = position;
+                if stroke.placed || brush_type.is_drag_shape() || brush_type == BrushType::Fill {
+                    return None;
+                }
+                (from, position, stroke.erase)
+            }
+            // Released. This is when lines and rectangles get drawn
+            (None, Some(stroke)) => {
+                let stroke = *stroke;
+                self.stroke = None;
+                if stroke.placed || !brush_type.is_drag_shape() {
+                    return None;
+                }
+                (stroke.start, stroke.last, stroke.erase)
+            }
+            (None, None) => return None,
+        };
+        let (from, to) = (from.into(), to.into());
+        Some(if erase {
+            AppCommand::Erase { from, to }
+        } else {
+            AppCommand::Paint { from, to }
+        })
+    }
+
+    /// Drags out the selection rectangle while the select button is held
+    fn update_selection(&mut self, held: bool) {
+        let position = self.mouse_to_world();
+        match (held, self.selecting) {
+            (true, false) => self.selection = Some((position, position)),
+            (true, true) => {
+                if let Some(selection) = self.selection.as_mut() {
+                    selection.1 = position;
                 }
-                self.total_scroll = 0.0;
             }
+            _ => {}
         }
+        self.selecting = held;
+    }
+
+    /// Returns None for painting and erasing, those are turned into strokes by stroke_command()
+    fn action_to_command(&self, action: Action, mouse_delta: Vec2) -> Option<AppCommand> {
+        // Keyboard panning covers the same part of the screen per second at every zoom level
+        let pan = PAN_SPEED * get_frame_time() / self.camera.zoom();
+        let command = match action {
+            Action::Quit => AppCommand::Quit,
+            Action::Clear => AppCommand::Clear,
+            Action::Paint | Action::Erase | Action::Select => return None,
+            Action::Copy | Action::Cut => {
+                let (from, to) = self.selection?;
+                let (from, to) = (from.into(), to.into());
+                if action == Action::Copy {
+                    AppCommand::Copy { from, to }
+                } else {
+                    AppCommand::Cut { from, to }
+                }
+            }
+            Action::PinInspector => {
+                // Clicking the pinned cell again unpins it
+                let position = self.mouse_to_world();
+                let pin = (self.pinned_cell != Some(position)).then_some(position.into());
+                AppCommand::PinInspector(pin)
+            }
+            Action::TogglePasting => AppCommand::TogglePasting,
+            Action::RotateClipboard => AppCommand::RotateClipboard,
+            Action::FlipClipboardHorizontal => AppCommand::FlipClipboard { vertical: false },
+            Action::FlipClipboardVertical => AppCommand::FlipClipboard { vertical: true },
+            Action::SelectMaterial(pixel_type) => AppCommand::SelectMaterial(pixel_type),
+            Action::NextMaterial => AppCommand::NextMaterial,
+            Action::PreviousMaterial => AppCommand::PreviousMaterial,
+            Action::NextBrushType => AppCommand::NextBrushType,
+            Action::PreviousBrushType => AppCommand::PreviousBrushType,
+            Action::GrowBrush => AppCommand::GrowBrush(1.0),
+            Action::ShrinkBrush => AppCommand::ShrinkBrush(1.0),
+            Action::TogglePause => AppCommand::TogglePause,
+            Action::Step => AppCommand::Step,
+            Action::ReloadKeybindings => AppCommand::ReloadKeybindings,
+            Action::ReloadScripts => AppCommand::ReloadScripts,
+            Action::ReloadPlugins => AppCommand::ReloadPlugins,
+            Action::ReloadRules => AppCommand::ReloadRules,
+            Action::ToggleConsole => return None,
+            Action::PanLeft => AppCommand::PanCamera { dx: -pan, dy: 0.0 },
+            Action::PanRight => AppCommand::PanCamera { dx: pan, dy: 0.0 },
+            Action::PanUp => AppCommand::PanCamera { dx: 0.0, dy: -pan },
+            Action::PanDown => AppCommand::PanCamera { dx: 0.0, dy: pan },
+            Action::DragCamera => {
+                // Convert the mouse movement from screen pixels to world pixels,
+                // and move the camera the other way so the world sticks to the mouse
+                let scale = self.camera.view_rect().w / self.screen_rect.w;
+                AppCommand::DragCamera {
+                    dx: -mouse_delta.x * scale,
+                    dy: -mouse_delta.y * scale,
+                }
+            }
+            Action::ZoomIn | Action::ZoomOut => {
+                let factor = if action == Action::ZoomIn {
+                    ZOOM_STEP
+                } else {
+                    1.0 / ZOOM_STEP
+                };
+                let world_position = self.mouse_to_world();
+                AppCommand::ZoomCamera {
+                    factor,
+                    x: world_position.x,
+                    y: world_position.y,
+                }
+            }
+        };
+        Some(command)
     }
 
-    fn handle_keyboard_input(&mut self) {
-        if is_key_released(KeyCode::Escape) {
-            self.quit();
+    /// Turns this frame's keyboard and mouse input into commands through the keybindings
+    /// and executes them
+    pub fn handle_input(&mut self) {
+        let (wheel_up, wheel_down) = self.scroll_steps();
+        let mouse: Vec2 = mouse_position().into();
+        let mouse_delta = mouse - self.last_mouse_position;
+        self.last_mouse_position = mouse;
+        // Don't paint through the ui windows
+        let over_ui = root_ui().is_mouse_over(mouse);
+        let mut actions = self.keybindings.triggered(wheel_up, wheel_down, !over_ui);
+        // Typing into the console shouldn't paint, pan or clear the world,
+        // and the escape that closes it shouldn't quit
+        let typing = self.console.is_open();
+        if actions.contains(&Action::ToggleConsole) {
+            self.console.toggle();
+        } else if typing {
+            let prefabs: Vec<String> = self
+                .prefabs
+                .prefabs()
+                .iter()
+                .map(|prefab| prefab.name().to_string())
+                .collect();
+            let names: Vec<&str> = prefabs.iter().map(String::as_str).collect();
+            if let Some(line) = self.console.handle_keys(&names) {
+                self.run_console(&line);
+            }
+        }
+        if typing || self.console.is_open() {
+            actions.clear();
         }
-        if is_key_pressed(KeyCode::C) {
-            self.chunk_grid.clear();
+        let erase = actions.contains(&Action::Erase);
+        let held = (erase || actions.contains(&Action::Paint)).then_some(erase);
+        self.update_selection(actions.contains(&Action::Select));
+        let mut commands: Vec<AppCommand> = self.stroke_command(held).into_iter().collect();
+        commands.extend(
+            actions
+                .into_iter()
+                .filter_map(|action| self.action_to_command(action, mouse_delta)),
+        );
+        // Closing the window counts as quitting
+        if is_quit_requested() {
+            commands.push(AppCommand::Quit);
+        }
+        for command in commands {
+            self.execute(command);
         }
+
+        // Move the camera towards where it should be, and use it for this frame
+        self.update_layout();
+        self.camera.update(get_frame_time());
+        self.render_camera = self.camera.to_camera2d(&self.render_target);
     }
 
-    pub fn handle_input(&mut self) {
-        self.handle_mouse_input();
-        self.handle_keyboard_input();
+    /// Runs a line typed into the console and prints what went wrong, if anything
+    fn run_console(&mut self, line: &str) {
+        let command = match parse(line) {
+            Ok(command) => command,
+            Err(error) => {
+                self.console.print(error);
+                return;
+            }
+        };
+        let commands = match command {
+            ConsoleCommand::Run(command) => vec![command],
+            ConsoleCommand::Tick(ticks) => vec![AppCommand::Steps(ticks)],
+            ConsoleCommand::ShowSeed => {
+                let seed = self.chunk_grid.seed();
+                self.console.print(format!("Seed: {seed}"));
+                return;
+            }
+            ConsoleCommand::SpawnPrefab { name, x, y } => match self.prefabs.get(&name) {
+                Some(prefab) => vec![AppCommand::PlacePattern {
+                    pattern: prefab.pattern().clone(),
+                    x,
+                    y,
+                }],
+                None => {
+                    self.console
+                        .print(format!("There is no prefab called {name}"));
+                    return;
+                }
+            },
+            ConsoleCommand::Help => {
+                for (_, usage) in COMMANDS {
+                    self.console.print(usage);
+                }
+                return;
+            }
+        };
+        for command in commands {
+            self.execute(command);
+        }
+    }
+
+    /// Reads the keybindings file again and reports anything wrong with it
+    fn reload_keybindings(&mut self) {
+        self.keybindings = Keybindings::load_or_create(&self.keybindings_path);
+        for problem in self.keybindings.problems() {
+            println!("Keybindings: {problem}");
+        }
     }
 
     pub fn start_drawing(&self) {
         set_camera(&self.render_camera);
     }
 
+    /// Shows what is about to happen to the world: the selection, the clipboard while pasting,
+    /// and the line or rectangle that is being dragged out
+    pub fn draw_previews(&self) {
+        if let Some((corner, opposite_corner)) = self.selection {
+            let min = corner.min(opposite_corner);
+            let size = (corner - opposite_corner).abs() + 1.0;
+            // One screen pixel wide at every zoom level
+            let thickness = self.camera.view_rect().w / self.screen_rect.w;
+            draw_rectangle_lines(min.x, min.y, size.x, size.y, thickness, WHITE);
+        }
+        if self.pasting
+            && let Some(clipboard) = &self.clipboard
+        {
+            clipboard.draw_ghost(clipboard.top_left_at(self.mouse_to_world()));
+        }
+
+        let Some(stroke) = self.stroke.filter(|stroke| !stroke.placed) else {
+            return;
+        };
+        let color = if stroke.erase {
+            PixelType::Air.to_color()
+        } else {
+            self.brush.pixel_type().to_color()
+        };
+        // Half transparent, so it's clear nothing has been placed yet
+        let color = Color::new(color.r, color.g, color.b, 0.5);
+        match self.brush.brush_type() {
+            BrushType::Line => {
+                // Pixels span from their position to the next one, so aim for their centers
+                let (start, end) = (stroke.start + 0.5, stroke.last + 0.5);
+                draw_line(start.x, start.y, end.x, end.y, self.brush.size(), color);
+            }
+            BrushType::Rectangle => {
+                let min = stroke.start.min(stroke.last);
+                let size = (stroke.start - stroke.last).abs() + 1.0;
+                draw_rectangle(min.x, min.y, size.x, size.y, color);
+            }
+            _ => {}
+        }
+    }
+
     pub fn stop_drawing(&self) {
         set_camera(&self.default_camera);
+        // Clear the screen, so the bars around the render target in integer scale mode are black
+        clear_background(BLACK);
 
         draw_texture_ex(
             &self.render_target.texture,
-            0.0,
-            0.0,
+            self.screen_rect.x,
+            self.screen_rect.y,
             WHITE,
             DrawTextureParams {
-                dest_size: Some(vec2(
-                    self.render_target.texture.width() * self.render_ratio.0, // We multiply the texture's dimensions by 4
-                    self.render_target.texture.height() * self.render_ratio.1, // Because the texture is a quarter of the size
-                )), // We should change this to dynamically multiply it by the ratio: screen / render target size
-                // The INITIAL sizes, not the scaled sizes after the camera projections
+                // Scale the render target up to the size update_layout() worked out for this window
+                dest_size: Some(self.screen_rect.size()),
                 ..Default::default()
             },
         );
     }
 
-    pub fn chunks(&self) -> &ChunkGrid {
-        return &self.chunk_grid;
+    pub fn clipboard(&self) -> Option<&Pattern> {
+        self.clipboard.as_ref()
+    }
+
+    pub fn pasting(&self) -> bool {
+        self.pasting
     }
 
-    pub fn chunks_mut(&mut self) -> &mut ChunkGrid {
-        return &mut self.chunk_grid;
+    pub fn plugin_brush(&self) -> Option<&str> {
+        self.plugin_brush.as_deref()
+    }
+
+    pub fn scenario(&self) -> Option<&ScenarioRun> {
+        self.scenario.as_ref()
+    }
+
+    pub fn console(&self) -> &Console {
+        &self.console
+    }
+
+    pub fn prefabs(&self) -> &PrefabLibrary {
+        &self.prefabs
+    }
+
+    pub fn chunks(&self) -> &ChunkGrid {
+        &self.chunk_grid
     }
 }
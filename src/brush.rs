@@ -1,80 +1,248 @@
 use macroquad::math::{Vec2, vec2};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
-use crate::{
-    CHUNK_SIZE,
-    pixel::PixelType,
-    pixel_grid::{Chunk, ChunkGrid},
-};
+use crate::{pixel::PixelType, pixel_grid::ChunkGrid};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
 pub enum BrushType {
+    /// A square anchored at its top left corner
     Pixel,
+    /// A filled circle centred on the cursor
     Circle,
+    CircleOutline,
+    /// A line from where the mouse was pressed to where it was released
+    Line,
+    /// A filled rectangle between where the mouse was pressed and where it was released
+    Rectangle,
+    /// Random pixels inside a circle
+    Spray,
+    /// Replaces the connected region of one material under the cursor
+    Fill,
 }
 impl BrushType {
-    pub fn as_str(&self) -> &str {
+    pub const ALL: [BrushType; 7] = [
+        BrushType::Pixel,
+        BrushType::Circle,
+        BrushType::CircleOutline,
+        BrushType::Line,
+        BrushType::Rectangle,
+        BrushType::Spray,
+        BrushType::Fill,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
         match *self {
             BrushType::Pixel => "Pixel",
             BrushType::Circle => "Circle",
+            BrushType::CircleOutline => "Circle outline",
+            BrushType::Line => "Line",
+            BrushType::Rectangle => "Rectangle",
+            BrushType::Spray => "Spray",
+            BrushType::Fill => "Fill",
         }
     }
 
+    /// Shapes that are dragged out from a start to an end point and only drawn when the mouse is released
+    pub fn is_drag_shape(&self) -> bool {
+        matches!(self, BrushType::Line | BrushType::Rectangle)
+    }
+
     pub fn next(&mut self) {
-        match *self {
-            BrushType::Pixel => *self = BrushType::Circle,
-            BrushType::Circle => *self = BrushType::Pixel,
-        }
+        let index = BrushType::ALL.iter().position(|b| b == self).unwrap_or(0);
+        *self = BrushType::ALL[(index + 1) % BrushType::ALL.len()];
     }
     pub fn previous(&mut self) {
+        let index = BrushType::ALL.iter().position(|b| b == self).unwrap_or(0);
+        *self = BrushType::ALL[(index + BrushType::ALL.len() - 1) % BrushType::ALL.len()];
+    }
+}
+/// Limits which pixels the brush is allowed to overwrite
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum BrushMode {
+    /// Overwrite everything
+    Normal,
+    /// Only change pixels of this material, and leave the rest alone
+    ReplaceOnly(PixelType),
+    /// Only paint into Air, so existing structures stay intact
+    EmptyOnly,
+}
+impl BrushMode {
+    pub const NAMES: [&'static str; 3] = ["Normal", "Replace only", "Empty only"];
+
+    pub fn index(&self) -> usize {
+        match self {
+            BrushMode::Normal => 0,
+            BrushMode::ReplaceOnly(_) => 1,
+            BrushMode::EmptyOnly => 2,
+        }
+    }
+
+    /// Whether a pixel that currently is `current` may be painted over
+    pub fn allows(&self, current: PixelType) -> bool {
         match *self {
-            BrushType::Circle => *self = BrushType::Pixel,
-            BrushType::Pixel => *self = BrushType::Circle,
+            BrushMode::Normal => true,
+            BrushMode::ReplaceOnly(pixel_type) => current == pixel_type,
+            BrushMode::EmptyOnly => current == PixelType::Air,
         }
     }
 }
+
 #[derive(Clone, Copy)]
 pub struct Brush {
     pixel_type: PixelType,
     brush_type: BrushType,
+    brush_mode: BrushMode,
     brush_size: f32,
+    // The fraction of pixels in the spray circle that gets painted each frame
+    spray_density: f32,
 }
 
 impl Brush {
-    pub fn new() -> Self {
+    pub fn new(
+        pixel_type: PixelType,
+        brush_type: BrushType,
+        brush_size: f32,
+        spray_density: f32,
+    ) -> Self {
         Self {
-            pixel_type: PixelType::Dirt,
-            brush_type: BrushType::Pixel,
-            brush_size: 5.0,
+            pixel_type,
+            brush_type,
+            brush_mode: BrushMode::Normal,
+            brush_size,
+            spray_density,
+        }
+    }
+
+    /// Sets a single pixel, if the brush mode allows overwriting what is there
+    fn place(&self, world_position: Vec2, chunk_grid: &mut ChunkGrid) {
+        if let Some(current) = chunk_grid.get_pixel(world_position)
+            && self.brush_mode.allows(current)
+        {
+            chunk_grid.set_pixel(world_position, self.pixel_type);
+        }
+    }
+
+    /// Draws a stroke from `from` to `to`. Stamping brushes are stamped at every pixel in between,
+    /// so moving the mouse fast doesn't leave gaps. Lines and rectangles use the two points as their ends
+    pub fn stroke(&self, from: Vec2, to: Vec2, chunk_grid: &mut ChunkGrid) {
+        match self.brush_type {
+            BrushType::Pixel | BrushType::Circle | BrushType::CircleOutline => {
+                for point in line_points(from, to) {
+                    self.draw(point, chunk_grid);
+                }
+            }
+            BrushType::Line => {
+                for point in line_points(from, to) {
+                    self.draw_circle(self.brush_size / 2.0, point, chunk_grid);
+                }
+            }
+            BrushType::Rectangle => self.draw_rectangle(from, to, chunk_grid),
+            // Spraying along the whole stroke would make it a lot denser than spraying on one spot
+            BrushType::Spray | BrushType::Fill => self.draw(to, chunk_grid),
         }
     }
 
     pub fn draw(&self, world_position: Vec2, chunk_grid: &mut ChunkGrid) {
         match self.brush_type {
             BrushType::Pixel => self.draw_pixel(world_position, chunk_grid),
-            BrushType::Circle => self.draw_circle(self.brush_size, world_position, chunk_grid),
+            BrushType::Circle | BrushType::Line => {
+                self.draw_circle(self.brush_size / 2.0, world_position, chunk_grid)
+            }
+            BrushType::CircleOutline => {
+                self.draw_circle_outline(self.brush_size / 2.0, world_position, chunk_grid)
+            }
+            BrushType::Rectangle => self.draw_rectangle(world_position, world_position, chunk_grid),
+            BrushType::Spray => self.draw_spray(self.brush_size / 2.0, world_position, chunk_grid),
+            BrushType::Fill => self.flood_fill(world_position, chunk_grid),
         }
     }
 
     pub fn draw_pixel(&self, world_position: Vec2, chunk_grid: &mut ChunkGrid) {
-        let mut pos = world_position;
+        let pos = world_position;
         for y in 0..self.brush_size as i32 {
             let dy = pos.y + y as f32;
             for x in 0..self.brush_size as i32 {
                 let dx = pos.x + x as f32;
-                chunk_grid.set_pixel(vec2(dx, dy), self.pixel_type())
+                self.place(vec2(dx, dy), chunk_grid)
             }
         }
     }
 
     pub fn draw_circle(&self, radius: f32, center: Vec2, chunk_grid: &mut ChunkGrid) {
-        // Naive circle drawing
-        for y in 0..CHUNK_SIZE.1 {
-            let dy = y as f32 - center.y;
-            for x in 0..CHUNK_SIZE.0 {
-                let dx = x as f32 - center.x;
-                let dist = (dx * dx + dy * dy).sqrt();
-                if dist <= radius - 1.0 as f32 {
-                    chunk_grid.set_pixel(center, self.pixel_type());
+        // Only loop over the square around the circle, and fill the pixels that are inside of it
+        let r = radius.ceil() as i32;
+        for y in -r..=r {
+            for x in -r..=r {
+                if (x * x + y * y) as f32 <= radius * radius {
+                    self.place(center + vec2(x as f32, y as f32), chunk_grid);
+                }
+            }
+        }
+    }
+
+    pub fn draw_circle_outline(&self, radius: f32, center: Vec2, chunk_grid: &mut ChunkGrid) {
+        // Same as draw_circle, but only the pixels within 1 pixel of the edge
+        let r = radius.ceil() as i32;
+        for y in -r..=r {
+            for x in -r..=r {
+                let dist = ((x * x + y * y) as f32).sqrt();
+                if dist <= radius && dist > radius - 1.0 {
+                    self.place(center + vec2(x as f32, y as f32), chunk_grid);
+                }
+            }
+        }
+    }
+
+    pub fn draw_rectangle(&self, corner: Vec2, opposite_corner: Vec2, chunk_grid: &mut ChunkGrid) {
+        let min = corner.min(opposite_corner);
+        let max = corner.max(opposite_corner);
+        for y in min.y as i32..=max.y as i32 {
+            for x in min.x as i32..=max.x as i32 {
+                self.place(vec2(x as f32, y as f32), chunk_grid);
+            }
+        }
+    }
+
+    pub fn draw_spray(&self, radius: f32, center: Vec2, chunk_grid: &mut ChunkGrid) {
+        // Pick random pixels in the square around the circle, and only keep the ones inside the circle
+        // We use the grid's RNG, so spraying plays out the same in a replay
+        let area = std::f32::consts::PI * radius * radius;
+        let count = (area * self.spray_density).ceil() as i32;
+        let r = radius.ceil() as i32;
+        for _ in 0..count {
+            let x = chunk_grid.random_range(-r, r + 1);
+            let y = chunk_grid.random_range(-r, r + 1);
+            if (x * x + y * y) as f32 <= radius * radius {
+                self.place(center + vec2(x as f32, y as f32), chunk_grid);
+            }
+        }
+    }
+
+    /// Replaces the material under `start`, and every pixel of that material connected to it, with the brush's material
+    pub fn flood_fill(&self, start: Vec2, chunk_grid: &mut ChunkGrid) {
+        let Some(target) = chunk_grid.get_pixel(start) else {
+            return;
+        };
+        // The whole region is the same material, so the brush mode either allows all of it or none of it
+        if target == self.pixel_type || !self.brush_mode.allows(target) {
+            return;
+        }
+        let mut queue = VecDeque::from([start]);
+        self.place(start, chunk_grid);
+        while let Some(position) = queue.pop_front() {
+            for offset in [
+                vec2(1.0, 0.0),
+                vec2(-1.0, 0.0),
+                vec2(0.0, 1.0),
+                vec2(0.0, -1.0),
+            ] {
+                let neighbour = position + offset;
+                // Pixels outside of the loaded chunks return None, so the fill stops at the edge of the world
+                if chunk_grid.get_pixel(neighbour) == Some(target) {
+                    // Setting it right away marks it as visited
+                    self.place(neighbour, chunk_grid);
+                    queue.push_back(neighbour);
                 }
             }
         }
@@ -92,6 +260,12 @@ impl Brush {
     pub fn brush_type_mut(&mut self) -> &mut BrushType {
         &mut self.brush_type
     }
+    pub fn brush_mode(&self) -> BrushMode {
+        self.brush_mode
+    }
+    pub fn set_brush_mode(&mut self, brush_mode: BrushMode) {
+        self.brush_mode = brush_mode;
+    }
     pub fn size(&self) -> f32 {
         self.brush_size
     }
@@ -99,6 +273,44 @@ impl Brush {
         self.brush_size += amount;
     }
     pub fn decrease_size(&mut self, amount: f32) {
-        self.brush_size -= amount;
+        // Don't let the brush disappear
+        self.brush_size = (self.brush_size - amount).max(1.0);
+    }
+    pub fn set_size(&mut self, size: f32) {
+        self.brush_size = size;
+    }
+    pub fn spray_density(&self) -> f32 {
+        self.spray_density
+    }
+    pub fn set_spray_density(&mut self, spray_density: f32) {
+        self.spray_density = spray_density;
+    }
+}
+
+/// All pixel positions on the line from `from` to `to`, including both ends (Bresenham's line algorithm)
+pub fn line_points(from: Vec2, to: Vec2) -> Vec<Vec2> {
+    let (mut x, mut y) = (from.x as i32, from.y as i32);
+    let (x1, y1) = (to.x as i32, to.y as i32);
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let step_x = if x < x1 { 1 } else { -1 };
+    let step_y = if y < y1 { 1 } else { -1 };
+    let mut error = dx + dy;
+    let mut points = vec![];
+    loop {
+        points.push(vec2(x as f32, y as f32));
+        if x == x1 && y == y1 {
+            break;
+        }
+        let error2 = 2 * error;
+        if error2 >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if error2 <= dx {
+            error += dx;
+            y += step_y;
+        }
     }
+    points
 }
@@ -0,0 +1,144 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 16.0;
+// How quickly the camera catches up with where it should be, higher is snappier
+const SMOOTHING: f32 = 15.0;
+
+/// The camera that looks at the world. Panning and zooming set a target,
+/// and the camera glides towards that target every frame
+pub struct WorldCamera {
+    view_size: Vec2,
+
+    center: Vec2,
+    zoom: f32,
+
+    target_center: Vec2,
+    target_zoom: f32,
+    // The world position that should stay under the cursor while zooming
+    zoom_anchor: Option<Vec2>,
+}
+
+impl WorldCamera {
+    /// Creates a camera showing `view_size` world pixels at zoom 1, with the world origin in the top left
+    pub fn new(view_size: Vec2) -> Self {
+        let center = view_size / 2.0;
+        Self {
+            view_size,
+            center,
+            zoom: 1.0,
+            target_center: center,
+            target_zoom: 1.0,
+            zoom_anchor: None,
+        }
+    }
+
+    /// Changes how many world pixels are visible at zoom 1, keeping the center where it is
+    pub fn set_view_size(&mut self, view_size: Vec2) {
+        self.view_size = view_size;
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// The part of the world that is currently visible
+    pub fn view_rect(&self) -> Rect {
+        let size = self.view_size / self.zoom;
+        Rect::new(
+            self.center.x - size.x / 2.0,
+            self.center.y - size.y / 2.0,
+            size.x,
+            size.y,
+        )
+    }
+
+    /// Moves the camera by `delta` world pixels, smoothly
+    pub fn pan(&mut self, delta: Vec2) {
+        self.target_center += delta;
+    }
+
+    /// Moves the camera by `delta` world pixels right away, so dragging feels attached to the mouse
+    pub fn drag(&mut self, delta: Vec2) {
+        self.center += delta;
+        self.target_center += delta;
+    }
+
+    /// Zooms in by `factor` (or out if it is below 1), keeping `anchor` in the same place on screen
+    pub fn zoom_at(&mut self, factor: f32, anchor: Vec2) {
+        self.target_zoom = (self.target_zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.zoom_anchor = Some(anchor);
+    }
+
+    /// Moves the camera a step closer to its target
+    pub fn update(&mut self, delta_time: f32) {
+        let t = 1.0 - (-SMOOTHING * delta_time).exp();
+
+        // Interpolate the zoom in log space, so zooming in and out feel equally fast
+        let mut zoom = (self.zoom.ln() + (self.target_zoom.ln() - self.zoom.ln()) * t).exp();
+        if (zoom - self.target_zoom).abs() < 0.001 {
+            zoom = self.target_zoom;
+        }
+        if let Some(anchor) = self.zoom_anchor {
+            // Scale the distance to the anchor along with the zoom, so the anchor doesn't move on screen
+            let scale = self.zoom / zoom;
+            self.center = anchor + (self.center - anchor) * scale;
+            self.target_center = anchor + (self.target_center - anchor) * scale;
+        }
+        self.zoom = zoom;
+        if self.zoom == self.target_zoom {
+            self.zoom_anchor = None;
+        }
+
+        self.center = self.center.lerp(self.target_center, t);
+    }
+
+    /// Builds the macroquad camera for the current view, drawing into `render_target`
+    pub fn to_camera2d(&self, render_target: &RenderTarget) -> Camera2D {
+        let mut camera = Camera2D::from_display_rect(self.view_rect());
+        camera.render_target = Some(render_target.clone());
+        camera
+    }
+}
+
+/// How the render target is fitted into the window
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ScalingMode {
+    /// Scale the render size by a whole number and put black bars around it, so every pixel is equally big
+    IntegerScale,
+    /// Fill the whole window, showing more of the world where the window is wider or taller than the render size
+    Fill,
+}
+
+impl ScalingMode {
+    pub const ALL: [ScalingMode; 2] = [ScalingMode::IntegerScale, ScalingMode::Fill];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScalingMode::IntegerScale => "Integer scale",
+            ScalingMode::Fill => "Fill",
+        }
+    }
+
+    /// Works out how big the render target should be for a window of `screen_size`,
+    /// and where on screen it is drawn. `render_size` is the least amount of world that is always visible
+    pub fn layout(&self, screen_size: Vec2, render_size: Vec2) -> (Vec2, Rect) {
+        let fit = (screen_size.x / render_size.x).min(screen_size.y / render_size.y);
+        match self {
+            ScalingMode::IntegerScale => {
+                let scale = fit.floor().max(1.0);
+                let size = render_size * scale;
+                let offset = ((screen_size - size) / 2.0).floor();
+                (render_size, Rect::new(offset.x, offset.y, size.x, size.y))
+            }
+            ScalingMode::Fill => {
+                // Grow the render target in the direction the window has room left,
+                // the last pixel may stick out a little over the edge of the window
+                let target_size = (screen_size / fit).ceil();
+                let size = target_size * fit;
+                (target_size, Rect::new(0.0, 0.0, size.x, size.y))
+            }
+        }
+    }
+}
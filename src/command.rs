@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    brush::{BrushMode, BrushType},
+    pixel::PixelType,
+    recorder::CaptureFormat,
+    selection::Pattern,
+};
+
+/// Everything that can be done to the app. Input sources (keyboard, mouse, ui, replays)
+/// turn what they receive into commands, and `App::execute` is the only place that applies them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AppCommand {
+    // World
+    /// A brush stroke from one world position to another. Stamping brushes are drawn all along it,
+    /// lines and rectangles use it as their two ends
+    Paint {
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    Erase {
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    Clear,
+    /// Fills a rectangle between two world positions with a material
+    Fill {
+        from: (f32, f32),
+        to: (f32, f32),
+        material: PixelType,
+    },
+    ClearChunk(i32, i32),
+    /// Places a pattern with its top left corner on a world position
+    PlacePattern {
+        pattern: Pattern,
+        x: f32,
+        y: f32,
+    },
+    /// Starts over with an empty world and a new seed
+    Restart(u64),
+    Save(String),
+    Load(String),
+    /// Restarts the world with a scenario file and plays it out
+    RunScenario(String),
+    StopScenario,
+
+    // Brush
+    SelectMaterial(PixelType),
+    NextMaterial,
+    PreviousMaterial,
+    SelectBrushType(BrushType),
+    NextBrushType,
+    PreviousBrushType,
+    SetBrushSize(f32),
+    GrowBrush(f32),
+    ShrinkBrush(f32),
+    SetSprayDensity(f32),
+    SetBrushMode(BrushMode),
+    /// Picks a brush tool from a plugin that clicking uses instead of the brush, or goes back to the brush
+    SelectPluginBrush(Option<String>),
+    /// Runs a plugin brush tool around a world position
+    UsePluginBrush {
+        name: String,
+        x: f32,
+        y: f32,
+    },
+
+    // Selection, corners and positions are in world pixels
+    Copy {
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    Cut {
+        from: (f32, f32),
+        to: (f32, f32),
+    },
+    /// Places the clipboard centred on a world position
+    Paste {
+        x: f32,
+        y: f32,
+    },
+    TogglePasting,
+    SetClipboard(Option<Pattern>),
+    RotateClipboard,
+    FlipClipboard {
+        vertical: bool,
+    },
+
+    // Prefabs
+    /// Saves the selection, or the clipboard if nothing is selected, to the prefab library
+    SavePrefab(String),
+    /// Puts a prefab from the library on the clipboard and starts pasting it
+    PlacePrefab(String),
+    ReloadPrefabs,
+    /// Turns a PNG into materials with the palette file, and starts pasting it
+    ImportImage(String),
+    /// Writes the selection, or the whole world if nothing is selected, to a PNG
+    ExportImage {
+        path: String,
+        scale: u32,
+    },
+
+    // Simulation
+    Pause(bool),
+    TogglePause,
+    Step,
+    /// Steps this many ticks at once, so a long run is a single command in a replay
+    Steps(u64),
+
+    // Camera, in world pixels
+    PanCamera {
+        dx: f32,
+        dy: f32,
+    },
+    DragCamera {
+        dx: f32,
+        dy: f32,
+    },
+    ZoomCamera {
+        factor: f32,
+        x: f32,
+        y: f32,
+    },
+
+    // Timeline
+    Scrub(usize),
+    BackToLive,
+    Branch,
+
+    // Replays
+    StartRecording,
+    StopRecording(String),
+    PlayReplay(String),
+    StopPlayback,
+
+    // Capturing frames of the selection, or of the visible area if nothing is selected
+    StartCapture {
+        path: String,
+        format: CaptureFormat,
+        every: u64,
+        scale: u32,
+        fps: u32,
+    },
+    StopCapture,
+
+    /// Keeps the inspector on a world position, or lets it follow the mouse again
+    PinInspector(Option<(f32, f32)>),
+
+    ReloadKeybindings,
+    /// Compiles the material scripts again, so edits show up without a restart
+    ReloadScripts,
+    /// Loads the plugins again
+    ReloadPlugins,
+    /// Compiles the automaton rules again
+    ReloadRules,
+    Quit,
+}
+
+impl AppCommand {
+    /// Whether this command changes the world or the brush, and should therefore
+    /// end up in a replay. Everything else only controls the app itself
+    pub fn is_recorded(&self) -> bool {
+        matches!(
+            self,
+            AppCommand::Paint { .. }
+                | AppCommand::Erase { .. }
+                | AppCommand::Clear
+                | AppCommand::Fill { .. }
+                | AppCommand::ClearChunk(..)
+                | AppCommand::PlacePattern { .. }
+                | AppCommand::SelectMaterial(_)
+                | AppCommand::NextMaterial
+                | AppCommand::PreviousMaterial
+                | AppCommand::SelectBrushType(_)
+                | AppCommand::NextBrushType
+                | AppCommand::PreviousBrushType
+                | AppCommand::SetBrushSize(_)
+                | AppCommand::GrowBrush(_)
+                | AppCommand::ShrinkBrush(_)
+                | AppCommand::SetSprayDensity(_)
+                | AppCommand::SetBrushMode(_)
+                | AppCommand::UsePluginBrush { .. }
+                | AppCommand::Copy { .. }
+                | AppCommand::Cut { .. }
+                | AppCommand::Paste { .. }
+                | AppCommand::SetClipboard(_)
+                | AppCommand::RotateClipboard
+                | AppCommand::FlipClipboard { .. }
+                | AppCommand::Pause(_)
+                | AppCommand::TogglePause
+                | AppCommand::Step
+                | AppCommand::Steps(_)
+        )
+    }
+}
@@ -0,0 +1,368 @@
+use macroquad::prelude::*;
+use std::collections::VecDeque;
+
+use crate::{brush::BrushType, command::AppCommand, pixel::PixelType};
+
+// How many lines of output the console keeps
+const OUTPUT_LINES: usize = 100;
+
+/// Every command with how to use it, shown by `help` and used for autocomplete
+pub const COMMANDS: [(&str, &str); 11] = [
+    ("help", "help"),
+    ("fill", "fill X0 Y0 X1 Y1 MATERIAL"),
+    ("clear", "clear | clear chunk X Y"),
+    ("seed", "seed | seed N (restarts the world)"),
+    ("tick", "tick N"),
+    ("save", "save FILE"),
+    ("load", "load FILE"),
+    (
+        "set",
+        "set brush.size|brush.density|brush.material|brush.type VALUE",
+    ),
+    ("spawn", "spawn prefab NAME X Y"),
+    ("pause", "pause | pause off"),
+    ("scenario", "scenario FILE | scenario stop"),
+];
+
+const SETTINGS: [&str; 4] = [
+    "brush.size",
+    "brush.density",
+    "brush.material",
+    "brush.type",
+];
+
+/// What a console line asks for. Most lines turn into app commands, the rest need to look at the app
+#[derive(Debug)]
+pub enum ConsoleCommand {
+    Run(AppCommand),
+    Tick(u64),
+    ShowSeed,
+    SpawnPrefab { name: String, x: f32, y: f32 },
+    Help,
+}
+
+/// A drop-down console for typing commands
+#[derive(Default)]
+pub struct Console {
+    open: bool,
+    input: String,
+    output: VecDeque<String>,
+    history: Vec<String>,
+    // Where we are in the history while going through it with the arrow keys
+    history_index: Option<usize>,
+}
+
+impl Console {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        // Throw away the key that opened the console, so it doesn't end up in the input
+        while get_char_pressed().is_some() {}
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn output(&self) -> &VecDeque<String> {
+        &self.output
+    }
+
+    pub fn print(&mut self, line: impl Into<String>) {
+        if self.output.len() == OUTPUT_LINES {
+            self.output.pop_front();
+        }
+        self.output.push_back(line.into());
+    }
+
+    /// Handles the typing of this frame, escape closes the console. Returns a line once enter is pressed.
+    /// `names` are extra words to autocomplete, like the prefab names
+    pub fn handle_keys(&mut self, names: &[&str]) -> Option<String> {
+        if is_key_pressed(KeyCode::Escape) {
+            self.toggle();
+            return None;
+        }
+        while let Some(character) = get_char_pressed() {
+            if !character.is_control() && character != '`' {
+                self.input.push(character);
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            self.input.pop();
+        }
+        if is_key_pressed(KeyCode::Tab) {
+            self.autocomplete(names);
+        }
+        if is_key_pressed(KeyCode::Up) && !self.history.is_empty() {
+            let index = self
+                .history_index
+                .map_or(self.history.len() - 1, |index| index.saturating_sub(1));
+            self.history_index = Some(index);
+            self.input = self.history[index].clone();
+        }
+        if is_key_pressed(KeyCode::Down)
+            && let Some(index) = self.history_index
+        {
+            if index + 1 < self.history.len() {
+                self.history_index = Some(index + 1);
+                self.input = self.history[index + 1].clone();
+            } else {
+                self.history_index = None;
+                self.input.clear();
+            }
+        }
+        if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+            let line = std::mem::take(&mut self.input);
+            self.history_index = None;
+            if line.trim().is_empty() {
+                return None;
+            }
+            self.print(format!("> {line}"));
+            if self.history.last() != Some(&line) {
+                self.history.push(line.clone());
+            }
+            return Some(line);
+        }
+        None
+    }
+
+    /// Completes the word being typed with a command, material, setting or one of `names`.
+    /// When more than one fits, they are all listed and the common start is filled in
+    fn autocomplete(&mut self, names: &[&str]) {
+        let start = self.input.rfind(' ').map_or(0, |space| space + 1);
+        let word = self.input[start..].to_lowercase();
+        let materials = PixelType::all()
+            .into_iter()
+            .map(|pixel_type| pixel_type.get().to_lowercase());
+        let mut candidates: Vec<String> = COMMANDS
+            .iter()
+            .map(|(command, _)| command.to_string())
+            .chain(materials)
+            .chain(SETTINGS.iter().map(|setting| setting.to_string()))
+            .chain(["chunk", "prefab"].map(String::from))
+            .chain(names.iter().map(|name| name.to_string()))
+            .filter(|candidate| candidate.to_lowercase().starts_with(&word))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        let Some(first) = candidates.first() else {
+            return;
+        };
+        // The longest start all candidates share
+        let mut common = first.clone();
+        for candidate in &candidates[1..] {
+            while !candidate.starts_with(&common) {
+                common.pop();
+            }
+        }
+        if candidates.len() > 1 {
+            self.print(candidates.join("  "));
+        }
+        self.input.truncate(start);
+        self.input.push_str(&common);
+        if candidates.len() == 1 {
+            self.input.push(' ');
+        }
+    }
+}
+
+/// Turns a console line into what it asks for, or a message about what is wrong with it
+pub fn parse(line: &str) -> Result<ConsoleCommand, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let usage = |name: &str| {
+        let usage = COMMANDS
+            .iter()
+            .find(|(command, _)| *command == name)
+            .map_or("", |(_, usage)| usage);
+        format!("Usage: {usage}")
+    };
+    let run = |command: AppCommand| Ok(ConsoleCommand::Run(command));
+    match words[..] {
+        ["help"] => Ok(ConsoleCommand::Help),
+        ["fill", x0, y0, x1, y1, material] => run(AppCommand::Fill {
+            from: (number(x0)?, number(y0)?),
+            to: (number(x1)?, number(y1)?),
+            material: parse_material(material)?,
+        }),
+        ["clear"] => run(AppCommand::Clear),
+        ["clear", "chunk", x, y] => run(AppCommand::ClearChunk(integer(x)?, integer(y)?)),
+        ["seed"] => Ok(ConsoleCommand::ShowSeed),
+        ["seed", seed] => run(AppCommand::Restart(
+            seed.parse()
+                .map_err(|_| format!("'{seed}' is not a seed"))?,
+        )),
+        ["tick", ticks] => {
+            Ok(ConsoleCommand::Tick(ticks.parse().map_err(|_| {
+                format!("'{ticks}' is not a number of ticks")
+            })?))
+        }
+        ["save", file] => run(AppCommand::Save(with_extension(file))),
+        ["load", file] => run(AppCommand::Load(with_extension(file))),
+        ["set", setting, value] => run(match setting {
+            // The same range as the settings window, bigger brushes take ages to draw
+            "brush.size" => AppCommand::SetBrushSize(number(value)?.clamp(1.0, 100.0)),
+            "brush.density" => AppCommand::SetSprayDensity(number(value)?.clamp(0.0, 1.0)),
+            "brush.material" => AppCommand::SelectMaterial(parse_material(value)?),
+            "brush.type" => AppCommand::SelectBrushType(parse_brush_type(value)?),
+            _ => {
+                return Err(format!(
+                    "Unknown setting '{setting}', try one of {}",
+                    SETTINGS.join(", ")
+                ));
+            }
+        }),
+        ["spawn", "prefab", name, x, y] => Ok(ConsoleCommand::SpawnPrefab {
+            name: name.to_string(),
+            x: number(x)?,
+            y: number(y)?,
+        }),
+        ["pause"] => run(AppCommand::Pause(true)),
+        ["pause", "off"] => run(AppCommand::Pause(false)),
+        ["scenario", "stop"] => run(AppCommand::StopScenario),
+        ["scenario", file] => run(AppCommand::RunScenario(with_extension(file))),
+        [command, ..] => match COMMANDS.iter().any(|(name, _)| *name == command) {
+            true => Err(usage(command)),
+            false => Err(format!("Unknown command '{command}', type help for a list")),
+        },
+        [] => Err(String::from("Type help for a list of commands")),
+    }
+}
+
+/// A finite number. Rust also parses "inf" and "NaN", and rounds huge numbers to infinity
+fn number(word: &str) -> Result<f32, String> {
+    word.parse()
+        .ok()
+        .filter(|number: &f32| number.is_finite())
+        .ok_or_else(|| format!("'{word}' is not a number"))
+}
+
+fn integer(word: &str) -> Result<i32, String> {
+    word.parse()
+        .map_err(|_| format!("'{word}' is not a whole number"))
+}
+
+fn parse_material(word: &str) -> Result<PixelType, String> {
+    PixelType::from_name(word).ok_or_else(|| format!("Unknown material '{word}'"))
+}
+
+fn parse_brush_type(word: &str) -> Result<BrushType, String> {
+    // Brush names can have spaces, so "circle_outline" and "circleoutline" both work
+    let word = word.replace(['_', '-'], "");
+    BrushType::ALL
+        .into_iter()
+        .find(|brush_type| {
+            brush_type
+                .as_str()
+                .replace(' ', "")
+                .eq_ignore_ascii_case(&word)
+        })
+        .ok_or_else(|| format!("Unknown brush type '{word}'"))
+}
+
+/// World and scenario files are ron files, so `save foo` writes `foo.ron`
+fn with_extension(file: &str) -> String {
+    if file.contains('.') {
+        file.to_string()
+    } else {
+        format!("{file}.ron")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(line: &str) -> String {
+        parse(line).unwrap_err()
+    }
+
+    fn command(line: &str) -> AppCommand {
+        match parse(line) {
+            Ok(ConsoleCommand::Run(command)) => command,
+            other => panic!("'{line}' gave {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_fill() {
+        let AppCommand::Fill { from, to, material } = command("fill 0 -5 10.5 20 Water") else {
+            panic!("not a fill");
+        };
+        assert_eq!((from, to), ((0.0, -5.0), (10.5, 20.0)));
+        assert_eq!(material, PixelType::Water);
+    }
+
+    #[test]
+    fn rejects_numbers_that_are_not_finite() {
+        for word in ["NaN", "nan", "inf", "-inf", "infinity", "1e39", "-1e39"] {
+            assert_eq!(
+                error(&format!("fill 0 0 {word} 1 water")),
+                format!("'{word}' is not a number")
+            );
+            assert!(parse(&format!("set brush.size {word}")).is_err());
+            assert!(parse(&format!("spawn prefab tower {word} 0")).is_err());
+        }
+    }
+
+    #[test]
+    fn clamps_the_brush_like_the_settings_window() {
+        for (value, size) in [("1e9", 100.0), ("0", 1.0), ("-3", 1.0), ("12.5", 12.5)] {
+            let AppCommand::SetBrushSize(parsed) = command(&format!("set brush.size {value}"))
+            else {
+                panic!("not a brush size");
+            };
+            assert_eq!(parsed, size);
+        }
+        let AppCommand::SetSprayDensity(density) = command("set brush.density 5") else {
+            panic!("not a density");
+        };
+        assert_eq!(density, 1.0);
+    }
+
+    #[test]
+    fn parses_ticks_and_whole_numbers() {
+        assert!(matches!(parse("tick 120"), Ok(ConsoleCommand::Tick(120))));
+        assert_eq!(error("tick -1"), "'-1' is not a number of ticks");
+        assert_eq!(error("tick 1e3"), "'1e3' is not a number of ticks");
+        assert!(matches!(
+            command("clear chunk -1 2"),
+            AppCommand::ClearChunk(-1, 2)
+        ));
+        assert_eq!(error("clear chunk 1.5 0"), "'1.5' is not a whole number");
+        assert_eq!(error("seed -1"), "'-1' is not a seed");
+    }
+
+    #[test]
+    fn looks_up_materials_and_brush_types() {
+        assert!(matches!(
+            command("set brush.material sAnD"),
+            AppCommand::SelectMaterial(PixelType::Sand)
+        ));
+        assert!(matches!(
+            command("set brush.type circle_outline"),
+            AppCommand::SelectBrushType(BrushType::CircleOutline)
+        ));
+        assert_eq!(error("fill 0 0 1 1 lava"), "Unknown material 'lava'");
+        assert!(error("set brush.colour red").starts_with("Unknown setting 'brush.colour'"));
+    }
+
+    #[test]
+    fn adds_the_world_file_extension() {
+        assert!(matches!(command("save world"), AppCommand::Save(file) if file == "world.ron"));
+        assert!(matches!(command("load old.sav"), AppCommand::Load(file) if file == "old.sav"));
+    }
+
+    #[test]
+    fn explains_unknown_and_misused_commands() {
+        assert_eq!(error("fill 0 0"), "Usage: fill X0 Y0 X1 Y1 MATERIAL");
+        assert_eq!(
+            error("jump"),
+            "Unknown command 'jump', type help for a list"
+        );
+        assert_eq!(error("   "), "Type help for a list of commands");
+        assert!(matches!(parse("help"), Ok(ConsoleCommand::Help)));
+    }
+}
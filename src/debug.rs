@@ -0,0 +1,110 @@
+use macroquad::prelude::*;
+
+use crate::{
+    chunk_size,
+    pixel_grid::{ChunkGrid, to_world_position},
+};
+
+// Movements are only a pixel long, so their arrows are drawn this many times longer to be visible
+const ARROW_LENGTH: f32 = 4.0;
+
+/// Debug views drawn over the world, each one can be switched on and off
+#[derive(Default)]
+pub struct Overlays {
+    pub chunk_borders: bool,
+    pub moved_cells: bool,
+    pub cross_chunk_moves: bool,
+    pub heat_map: bool,
+    pub rigid_bodies: bool,
+}
+
+impl Overlays {
+    /// Draws the enabled overlays. Call this with the render camera set, `zoom` keeps lines and text
+    /// the same size on screen
+    pub fn draw(&self, chunk_grid: &ChunkGrid, zoom: f32) {
+        let size = vec2(chunk_size().0 as f32, chunk_size().1 as f32);
+        let line = 1.0 / zoom;
+
+        if self.heat_map {
+            // Color each chunk by how long its update took, compared to the slowest chunk
+            let slowest = chunk_grid
+                .chunks()
+                .map(|(_, chunk)| chunk.update_cost())
+                .max()
+                .unwrap_or_default()
+                .as_secs_f32();
+            for (key, chunk) in chunk_grid.chunks() {
+                let cost = if slowest > 0.0 {
+                    chunk.update_cost().as_secs_f32() / slowest
+                } else {
+                    0.0
+                };
+                let corner = vec2(key.0 as f32, key.1 as f32) * size;
+                draw_rectangle(
+                    corner.x,
+                    corner.y,
+                    size.x,
+                    size.y,
+                    Color::new(cost, 0.0, 1.0 - cost, 0.35),
+                );
+            }
+        }
+
+        if self.moved_cells {
+            for (key, chunk) in chunk_grid.chunks() {
+                for position in chunk.moved_cells() {
+                    let (x, y) = to_world_position(*key, *position);
+                    draw_rectangle(x as f32, y as f32, 1.0, 1.0, Color::new(1.0, 1.0, 0.0, 0.6));
+                }
+            }
+        }
+
+        if self.cross_chunk_moves {
+            for (from, to) in chunk_grid.cross_chunk_moves() {
+                // Start in the middle of the pixel the movement came from
+                let start = vec2(from.0 as f32, from.1 as f32) + 0.5;
+                let direction = vec2((to.0 - from.0) as f32, (to.1 - from.1) as f32);
+                let end = start + direction * ARROW_LENGTH;
+                draw_line(start.x, start.y, end.x, end.y, line, MAGENTA);
+                draw_circle(end.x, end.y, line * 2.0, MAGENTA);
+            }
+        }
+
+        if self.rigid_bodies {
+            for body in chunk_grid.bodies() {
+                for (x, y) in body.cells() {
+                    draw_rectangle(
+                        *x as f32,
+                        *y as f32,
+                        1.0,
+                        1.0,
+                        Color::new(1.0, 0.5, 0.0, 0.4),
+                    );
+                }
+                // A line from the centre of mass shows which way the body is turned
+                let centre = body.position();
+                let end = centre + Vec2::from_angle(body.angle()) * ARROW_LENGTH * 2.0;
+                draw_line(centre.x, centre.y, end.x, end.y, line, ORANGE);
+                draw_circle(centre.x, centre.y, line * 2.0, ORANGE);
+            }
+        }
+
+        if self.chunk_borders {
+            for key in chunk_grid.chunks().map(|(key, _)| key) {
+                let corner = vec2(key.0 as f32, key.1 as f32) * size;
+                draw_rectangle_lines(corner.x, corner.y, size.x, size.y, line * 2.0, RED);
+                draw_text_ex(
+                    &format!("{key:?}"),
+                    corner.x + line * 4.0,
+                    corner.y + line * 10.0,
+                    TextParams {
+                        font_size: 8,
+                        font_scale: line,
+                        color: RED,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use macroquad::{prelude::*, rand::RandGenerator};
+use std::{error::Error, path::PathBuf};
+
+use crate::{
+    config_path,
+    image_io::export_png,
+    pixel_grid::ChunkGrid,
+    plugin::PluginHost,
+    recorder::{CaptureFormat, FrameRecorder},
+    rules::RuleSet,
+    save::load_world,
+    scenario::{Scenario, ScenarioRun},
+    scripting::MaterialScripts,
+};
+
+const USAGE: &str = "Usage: sandbox --headless [--seed N] [--load WORLD] [--scripts FOLDER] [--plugins FOLDER] [--rules FOLDER] [--scenario FILE] [--ticks N] \
+[--export PNG] [--scale N] [--region X,Y,W,H] \
+[--capture FILE] [--format gif|apng|png] [--every N] [--fps N]";
+
+/// What to do in a headless run, read from the command line
+struct Options {
+    seed: u64,
+    load: Option<String>,
+    // The material scripts, plugins and rules folders, the same ones as the app uses if not set
+    scripts: PathBuf,
+    plugins: PathBuf,
+    rules: PathBuf,
+    // Replaces the seed and loaded world, and runs at least until the scenario is done
+    scenario: Option<String>,
+    ticks: u64,
+    export: Option<String>,
+    scale: u32,
+    // The top left corner and size of the region to export and capture, the whole world if not set
+    region: Option<(Vec2, Vec2)>,
+    capture: Option<String>,
+    format: CaptureFormat,
+    every: u64,
+    fps: u32,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut options = Self {
+            seed: 0,
+            load: None,
+            scripts: config_path("scripts"),
+            plugins: config_path("plugins"),
+            rules: config_path("rules"),
+            scenario: None,
+            ticks: 0,
+            export: None,
+            scale: 1,
+            region: None,
+            capture: None,
+            format: CaptureFormat::Gif,
+            every: 1,
+            fps: 30,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{arg} needs a value\n{USAGE}"))
+            };
+            match arg.as_str() {
+                "--seed" => options.seed = value()?.parse()?,
+                "--load" => options.load = Some(value()?.clone()),
+                "--scripts" => options.scripts = PathBuf::from(value()?),
+                "--plugins" => options.plugins = PathBuf::from(value()?),
+                "--rules" => options.rules = PathBuf::from(value()?),
+                "--scenario" => options.scenario = Some(value()?.clone()),
+                "--ticks" => options.ticks = value()?.parse()?,
+                "--export" => options.export = Some(value()?.clone()),
+                "--scale" => options.scale = value()?.parse()?,
+                "--region" => {
+                    let numbers = value()?
+                        .split(',')
+                        .map(|number| number.trim().parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let [x, y, w, h] = numbers[..] else {
+                        return Err(format!("--region needs X,Y,W,H\n{USAGE}").into());
+                    };
+                    if !(w > 0.0 && h > 0.0) {
+                        return Err(
+                            format!("--region needs a width and height above 0\n{USAGE}").into(),
+                        );
+                    }
+                    options.region = Some((vec2(x, y), vec2(x + w - 1.0, y + h - 1.0)));
+                }
+                "--capture" => options.capture = Some(value()?.clone()),
+                "--format" => {
+                    options.format = match value()?.as_str() {
+                        "gif" => CaptureFormat::Gif,
+                        "apng" => CaptureFormat::Apng,
+                        "png" => CaptureFormat::PngSequence,
+                        format => {
+                            return Err(format!("Unknown format {format}\n{USAGE}").into());
+                        }
+                    }
+                }
+                "--every" => options.every = value()?.parse()?,
+                "--fps" => options.fps = value()?.parse()?,
+                _ => return Err(format!("Unknown argument {arg}\n{USAGE}").into()),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Runs the simulation without a window: loads or creates a world, simulates it and writes the results
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args)?;
+
+    let rng = RandGenerator::new();
+    rng.srand(options.seed);
+    let mut chunk_grid = ChunkGrid::new(options.seed, rng);
+    chunk_grid.set_scripts(MaterialScripts::load(&options.scripts));
+    chunk_grid.set_plugins(PluginHost::load(&options.plugins));
+    chunk_grid.set_rules(RuleSet::load(&options.rules));
+    if let Some(path) = &options.load {
+        load_world(&mut chunk_grid, path)?;
+        println!("Loaded {path} at tick {}", chunk_grid.tick());
+    }
+    let mut scenario = match &options.scenario {
+        Some(path) => {
+            let scenario = Scenario::load(path)?;
+            chunk_grid.restart(scenario.seed());
+            println!("Running scenario {path}");
+            Some(ScenarioRun::start(scenario, &mut chunk_grid)?)
+        }
+        None => None,
+    };
+    let ticks = match &scenario {
+        Some(run) => options.ticks.max(run.progress().1),
+        None => options.ticks,
+    };
+
+    let Some(region) = options.region.or(chunk_grid.bounds()) else {
+        return Err("The world is empty".into());
+    };
+
+    let mut capture = match &options.capture {
+        Some(path) => Some(FrameRecorder::start(
+            options.format,
+            path,
+            region,
+            options.every,
+            options.scale,
+            options.fps,
+        )?),
+        None => None,
+    };
+    for _ in 0..ticks {
+        chunk_grid.update();
+        if let Some(run) = scenario.as_mut() {
+            run.update(&mut chunk_grid);
+        }
+        if let Some(recorder) = capture.as_mut()
+            && let Err(error) = recorder.capture(&chunk_grid)
+        {
+            // Finish the file with the frames we have, like the app does
+            if let Some(recorder) = capture.take() {
+                let frames = recorder.frames();
+                println!("Captured {frames} frames to {}", recorder.finish()?);
+            }
+            return Err(error);
+        }
+    }
+    println!("Simulated until tick {}", chunk_grid.tick());
+    if let Some(capture) = capture {
+        let frames = capture.frames();
+        println!("Captured {frames} frames to {}", capture.finish()?);
+    }
+
+    if let Some(path) = &options.export {
+        export_png(&chunk_grid, region.0, region.1, options.scale, path)?;
+        println!("Exported to {path}");
+    }
+    if let Some(run) = scenario {
+        let failures = run.failures();
+        let total = run.outcomes().len();
+        if failures > 0 {
+            return Err(format!("{failures} of {total} scenario checks failed").into());
+        }
+        println!("All {total} scenario checks passed");
+    }
+    Ok(())
+}
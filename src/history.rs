@@ -0,0 +1,191 @@
+use crate::{pixel::PixelType, rigid_body::RigidBody};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    rc::Rc,
+};
+
+/// A copy of the whole chunk grid at a certain tick, with the rigid bodies moving through it.
+/// The chunk data is reference counted, so chunks that did not change
+/// between snapshots share the same memory
+pub struct Snapshot {
+    tick: u64,
+    chunks: BTreeMap<(i32, i32), Rc<Vec<PixelType>>>,
+    bodies: Vec<RigidBody>,
+}
+
+impl Snapshot {
+    pub fn new(
+        tick: u64,
+        chunks: BTreeMap<(i32, i32), Rc<Vec<PixelType>>>,
+        bodies: Vec<RigidBody>,
+    ) -> Self {
+        Self {
+            tick,
+            chunks,
+            bodies,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn chunks(&self) -> &BTreeMap<(i32, i32), Rc<Vec<PixelType>>> {
+        &self.chunks
+    }
+
+    pub fn bodies(&self) -> &[RigidBody] {
+        &self.bodies
+    }
+}
+
+/// Ring buffer of snapshots, taken every `interval` ticks.
+/// While scrubbing through the timeline the grid shows an older snapshot,
+/// and the present is kept aside in `live` so we can jump back to it
+pub struct Timeline {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+    interval: u64,
+    cursor: Option<usize>,
+    live: Option<Snapshot>,
+}
+
+impl Timeline {
+    pub fn new(capacity: usize, interval: u64) -> Self {
+        Self {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            interval,
+            cursor: None,
+            live: None,
+        }
+    }
+
+    /// Called after every tick. Only keeps a snapshot every `interval` ticks
+    /// and drops the oldest one once the buffer is full
+    pub fn record(&mut self, snapshot: Snapshot) {
+        if !snapshot.tick().is_multiple_of(self.interval) {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Snapshot> {
+        self.snapshots.get(index)
+    }
+
+    /// The snapshot that is currently shown, or None if we are showing the present
+    pub fn cursor(&self) -> Option<usize> {
+        self.cursor
+    }
+
+    pub fn is_scrubbing(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Moves the cursor to a snapshot. The first time we leave the present,
+    /// the caller hands us the present so we can return to it later
+    pub fn scrub(&mut self, index: usize, present: impl FnOnce() -> Snapshot) -> Option<&Snapshot> {
+        if index >= self.snapshots.len() {
+            return None;
+        }
+        if self.live.is_none() {
+            self.live = Some(present());
+        }
+        self.cursor = Some(index);
+        self.snapshots.get(index)
+    }
+
+    /// Stops scrubbing and returns the present that was kept aside
+    pub fn back_to_live(&mut self) -> Option<Snapshot> {
+        self.cursor = None;
+        self.live.take()
+    }
+
+    /// Makes the snapshot under the cursor the new present.
+    /// Everything recorded after it is thrown away
+    pub fn branch(&mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            self.snapshots.truncate(cursor + 1);
+        }
+        self.live = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.cursor = None;
+        self.live = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(tick: u64) -> Snapshot {
+        Snapshot::new(tick, Default::default(), vec![])
+    }
+
+    fn timeline(ticks: impl IntoIterator<Item = u64>) -> Timeline {
+        let mut timeline = Timeline::new(4, 10);
+        for tick in ticks {
+            timeline.record(snapshot(tick));
+        }
+        timeline
+    }
+
+    fn ticks(timeline: &Timeline) -> Vec<u64> {
+        (0..timeline.len())
+            .map(|index| timeline.get(index).unwrap().tick())
+            .collect()
+    }
+
+    #[test]
+    fn records_every_interval_and_drops_the_oldest() {
+        let timeline = timeline(0..=60);
+        assert_eq!(ticks(&timeline), [30, 40, 50, 60]);
+    }
+
+    #[test]
+    fn scrubbing_keeps_the_present_aside() {
+        let mut timeline = timeline([10, 20, 30]);
+        assert_eq!(timeline.scrub(0, || snapshot(35)).unwrap().tick(), 10);
+        // The present is only taken the first time we leave it
+        assert_eq!(timeline.scrub(1, || unreachable!()).unwrap().tick(), 20);
+        assert!(timeline.scrub(3, || unreachable!()).is_none());
+        assert_eq!(timeline.cursor(), Some(1));
+
+        assert_eq!(timeline.back_to_live().unwrap().tick(), 35);
+        assert!(!timeline.is_scrubbing());
+        assert_eq!(ticks(&timeline), [10, 20, 30]);
+    }
+
+    #[test]
+    fn branching_after_a_scrub_drops_the_future() {
+        let mut timeline = timeline([10, 20, 30, 40]);
+        timeline.scrub(2, || snapshot(45));
+        timeline.scrub(1, || unreachable!());
+        timeline.branch();
+
+        assert!(!timeline.is_scrubbing());
+        assert!(timeline.back_to_live().is_none());
+        assert_eq!(ticks(&timeline), [10, 20]);
+
+        timeline.record(snapshot(30));
+        assert_eq!(ticks(&timeline), [10, 20, 30]);
+    }
+
+    #[test]
+    fn branching_without_scrubbing_keeps_everything() {
+        let mut timeline = timeline([10, 20]);
+        timeline.branch();
+        assert_eq!(ticks(&timeline), [10, 20]);
+    }
+}
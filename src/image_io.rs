@@ -0,0 +1,173 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, fs::File, io::BufWriter, path::Path};
+
+use crate::{pixel::PixelType, pixel_grid::ChunkGrid, selection::Pattern};
+
+/// Which image color stands for which material when importing images
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Palette {
+    entries: Vec<(PixelType, (u8, u8, u8))>,
+}
+
+impl Default for Palette {
+    /// The colors the materials are drawn with. Air is left out, transparent pixels become Air instead
+    fn default() -> Self {
+        let entries = PixelType::all()
+            .iter()
+            .filter(|pixel_type| **pixel_type != PixelType::Air)
+            .map(|pixel_type| {
+                let [r, g, b, _] = pixel_type.to_color().into();
+                (*pixel_type, (r, g, b))
+            })
+            .collect();
+        Self { entries }
+    }
+}
+
+impl Palette {
+    /// Loads the palette from `path`. If the file doesn't exist yet it is created with the default colors.
+    /// If it can't be read we fall back to the default colors
+    pub fn load_or_create(path: &Path) -> Self {
+        if !path.exists() {
+            let defaults = Self::default();
+            match defaults.save(path) {
+                Ok(()) => println!("Wrote default palette to {}", path.display()),
+                Err(error) => println!("Failed to write palette to {}: {error}", path.display()),
+            }
+            return defaults;
+        }
+        match Self::load(path) {
+            Ok(palette) if !palette.entries.is_empty() => palette,
+            Ok(_) => {
+                println!("Palette {} is empty, using the defaults", path.display());
+                Self::default()
+            }
+            Err(error) => {
+                println!(
+                    "Failed to load palette from {}: {error}, using the defaults",
+                    path.display()
+                );
+                Self::default()
+            }
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// The material whose color is closest to `color`
+    pub fn nearest(&self, color: [u8; 4]) -> PixelType {
+        // Mostly transparent pixels are empty space
+        if color[3] < 128 {
+            return PixelType::Air;
+        }
+        let distance = |(r, g, b): (u8, u8, u8)| {
+            let dr = r as i32 - color[0] as i32;
+            let dg = g as i32 - color[1] as i32;
+            let db = b as i32 - color[2] as i32;
+            dr * dr + dg * dg + db * db
+        };
+        self.entries
+            .iter()
+            .min_by_key(|(_, rgb)| distance(*rgb))
+            .map_or(PixelType::Air, |(pixel_type, _)| *pixel_type)
+    }
+}
+
+/// Reads the PNG at `path` and turns every image pixel into the nearest material in `palette`
+pub fn import_png(path: &str, palette: &Palette) -> Result<Pattern, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let image = Image::from_file_with_format(&bytes, Some(ImageFormat::Png))?;
+    let pixels = image
+        .get_image_data()
+        .iter()
+        .map(|color| palette.nearest(*color))
+        .collect();
+    Ok(Pattern::new(
+        image.width as usize,
+        image.height as usize,
+        pixels,
+    ))
+}
+
+/// The pixels between two corners (both included) as RGBA bytes, row by row, every pixel as a
+/// `scale` by `scale` square. Uses the colors the chunks are drawn with, and doesn't need a window.
+/// Returns the width and height of the image along with the bytes, or an error when the image
+/// would be too big to hold
+pub fn region_rgba(
+    chunk_grid: &ChunkGrid,
+    corner: Vec2,
+    opposite_corner: Vec2,
+    scale: u32,
+) -> Result<(u32, u32, Vec<u8>), Box<dyn Error>> {
+    let scale = scale.max(1);
+    let min = corner.min(opposite_corner);
+    let max = corner.max(opposite_corner);
+    let width = (max.x - min.x) as u32 + 1;
+    let height = (max.y - min.y) as u32 + 1;
+
+    let too_big = || format!("A {width} by {height} region at scale {scale} is too big to export");
+    let scaled_width = width.checked_mul(scale).ok_or_else(too_big)?;
+    let scaled_height = height.checked_mul(scale).ok_or_else(too_big)?;
+    let row_bytes = (scaled_width as usize).checked_mul(4).ok_or_else(too_big)?;
+    let total_bytes = row_bytes
+        .checked_mul(scaled_height as usize)
+        .ok_or_else(too_big)?;
+
+    let mut bytes = Vec::new();
+    bytes
+        .try_reserve_exact(total_bytes)
+        .map_err(|_| too_big())?;
+    for y in 0..height {
+        let mut row = Vec::with_capacity(row_bytes);
+        for x in 0..width {
+            // Positions outside of the loaded chunks are left transparent, like Air
+            let color = chunk_grid
+                .get_pixel(min + vec2(x as f32, y as f32))
+                .unwrap_or(PixelType::Air)
+                .to_color();
+            let rgba: [u8; 4] = color.into();
+            for _ in 0..scale {
+                row.extend_from_slice(&rgba);
+            }
+        }
+        for _ in 0..scale {
+            bytes.extend_from_slice(&row);
+        }
+    }
+    Ok((scaled_width, scaled_height, bytes))
+}
+
+/// Writes RGBA bytes to a PNG at `path`
+pub fn write_png(path: &str, width: u32, height: u32, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(bytes)?;
+    Ok(())
+}
+
+/// Writes the pixels between two corners (both included) to a PNG at `path`, every pixel as a
+/// `scale` by `scale` square
+pub fn export_png(
+    chunk_grid: &ChunkGrid,
+    corner: Vec2,
+    opposite_corner: Vec2,
+    scale: u32,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height, bytes) = region_rgba(chunk_grid, corner, opposite_corner, scale)?;
+    write_png(path, width, height, &bytes)
+}
@@ -0,0 +1,587 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use crate::pixel::PixelType;
+
+/// Something a binding can make the app do
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Action {
+    Quit,
+    Clear,
+    Paint,
+    Erase,
+    SelectMaterial(PixelType),
+    NextMaterial,
+    PreviousMaterial,
+    NextBrushType,
+    PreviousBrushType,
+    GrowBrush,
+    ShrinkBrush,
+    Select,
+    PinInspector,
+    Copy,
+    Cut,
+    TogglePasting,
+    RotateClipboard,
+    FlipClipboardHorizontal,
+    FlipClipboardVertical,
+    TogglePause,
+    Step,
+    ReloadKeybindings,
+    ReloadScripts,
+    ReloadPlugins,
+    ReloadRules,
+    ToggleConsole,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    DragCamera,
+    ZoomIn,
+    ZoomOut,
+}
+
+impl Action {
+    /// Continuous actions fire every frame the trigger is held down,
+    /// all other actions only fire on the frame the trigger is pressed
+    pub fn is_continuous(&self) -> bool {
+        matches!(
+            self,
+            Action::Paint
+                | Action::Erase
+                | Action::Select
+                | Action::PanLeft
+                | Action::PanRight
+                | Action::PanUp
+                | Action::PanDown
+                | Action::DragCamera
+        )
+    }
+}
+
+/// The input that triggers a binding. Keys and mouse buttons are stored by name,
+/// like "C", "Key1", "Escape" or "F5" and "Left", "Middle" or "Right"
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Trigger {
+    Key(String),
+    Mouse(String),
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+}
+
+impl Modifier {
+    fn is_down(&self) -> bool {
+        match self {
+            Modifier::Shift => is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift),
+            Modifier::Ctrl => {
+                is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl)
+            }
+            Modifier::Alt => is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Binding {
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub modifiers: Vec<Modifier>,
+    pub action: Action,
+}
+
+impl Binding {
+    fn new(trigger: Trigger, modifiers: &[Modifier], action: Action) -> Self {
+        Self {
+            trigger,
+            modifiers: modifiers.to_vec(),
+            action,
+        }
+    }
+
+    /// A chord only matches when exactly its modifiers are held,
+    /// so Shift+wheel doesn't also fire the plain wheel binding
+    fn modifiers_match(&self) -> bool {
+        [Modifier::Shift, Modifier::Ctrl, Modifier::Alt]
+            .iter()
+            .all(|modifier| modifier.is_down() == self.modifiers.contains(modifier))
+    }
+
+    /// A readable name for the trigger and its modifiers, like "Shift+WheelUp".
+    /// The modifiers always come in the same order, so Ctrl+Shift+X and Shift+Ctrl+X
+    /// are the same chord
+    pub fn chord(&self) -> String {
+        let mut chord = String::new();
+        for modifier in [Modifier::Shift, Modifier::Ctrl, Modifier::Alt] {
+            if self.modifiers.contains(&modifier) {
+                chord.push_str(&format!("{modifier:?}+"));
+            }
+        }
+        match &self.trigger {
+            Trigger::Key(name) => chord.push_str(name),
+            Trigger::Mouse(name) => chord.push_str(&format!("Mouse{name}")),
+            Trigger::WheelUp => chord.push_str("WheelUp"),
+            Trigger::WheelDown => chord.push_str("WheelDown"),
+        }
+        chord
+    }
+}
+
+/// A trigger with its key or button name looked up
+#[derive(Clone, Copy)]
+enum Input {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    WheelUp,
+    WheelDown,
+    Unknown,
+}
+
+impl Input {
+    fn from_trigger(trigger: &Trigger) -> Self {
+        match trigger {
+            Trigger::Key(name) => key_code(name).map_or(Input::Unknown, Input::Key),
+            Trigger::Mouse(name) => mouse_button(name).map_or(Input::Unknown, Input::Mouse),
+            Trigger::WheelUp => Input::WheelUp,
+            Trigger::WheelDown => Input::WheelDown,
+        }
+    }
+}
+
+pub struct Keybindings {
+    bindings: Vec<Binding>,
+    // The looked up input for every binding, so we don't search the key names every frame
+    inputs: Vec<Input>,
+}
+
+impl Keybindings {
+    /// The controls the app shipped with before they were configurable
+    pub fn defaults() -> Self {
+        use Modifier::*;
+        let key = |name: &str| Trigger::Key(name.to_string());
+        let mouse = |name: &str| Trigger::Mouse(name.to_string());
+        let bindings = vec![
+            Binding::new(key("Escape"), &[], Action::Quit),
+            Binding::new(key("C"), &[], Action::Clear),
+            Binding::new(mouse("Left"), &[], Action::Paint),
+            Binding::new(mouse("Right"), &[], Action::Erase),
+            Binding::new(mouse("Left"), &[Shift], Action::Select),
+            Binding::new(mouse("Left"), &[Ctrl], Action::PinInspector),
+            Binding::new(key("C"), &[Ctrl], Action::Copy),
+            Binding::new(key("X"), &[Ctrl], Action::Cut),
+            Binding::new(key("V"), &[Ctrl], Action::TogglePasting),
+            Binding::new(key("R"), &[], Action::RotateClipboard),
+            Binding::new(key("F"), &[], Action::FlipClipboardHorizontal),
+            Binding::new(key("F"), &[Shift], Action::FlipClipboardVertical),
+            Binding::new(Trigger::WheelUp, &[], Action::NextMaterial),
+            Binding::new(Trigger::WheelDown, &[], Action::PreviousMaterial),
+            Binding::new(Trigger::WheelUp, &[Shift], Action::NextBrushType),
+            Binding::new(Trigger::WheelDown, &[Shift], Action::PreviousBrushType),
+            // Not on Alt+wheel, window managers like to grab that one
+            Binding::new(Trigger::WheelUp, &[Shift, Ctrl], Action::GrowBrush),
+            Binding::new(Trigger::WheelDown, &[Shift, Ctrl], Action::ShrinkBrush),
+            Binding::new(key("RightBracket"), &[], Action::GrowBrush),
+            Binding::new(key("LeftBracket"), &[], Action::ShrinkBrush),
+            Binding::new(key("Key1"), &[], Action::SelectMaterial(PixelType::Sand)),
+            Binding::new(key("Key2"), &[], Action::SelectMaterial(PixelType::Water)),
+            Binding::new(key("Key3"), &[], Action::SelectMaterial(PixelType::Dirt)),
+            Binding::new(key("Key4"), &[], Action::SelectMaterial(PixelType::Stone)),
+            Binding::new(key("Key5"), &[], Action::SelectMaterial(PixelType::Grass)),
+            Binding::new(key("Key6"), &[], Action::SelectMaterial(PixelType::Air)),
+            Binding::new(key("Space"), &[], Action::TogglePause),
+            Binding::new(key("Period"), &[], Action::Step),
+            Binding::new(key("F5"), &[], Action::ReloadKeybindings),
+            Binding::new(key("F6"), &[], Action::ReloadScripts),
+            Binding::new(key("F7"), &[], Action::ReloadPlugins),
+            Binding::new(key("F8"), &[], Action::ReloadRules),
+            Binding::new(key("GraveAccent"), &[], Action::ToggleConsole),
+            Binding::new(key("A"), &[], Action::PanLeft),
+            Binding::new(key("D"), &[], Action::PanRight),
+            Binding::new(key("W"), &[], Action::PanUp),
+            Binding::new(key("S"), &[], Action::PanDown),
+            Binding::new(key("Left"), &[], Action::PanLeft),
+            Binding::new(key("Right"), &[], Action::PanRight),
+            Binding::new(key("Up"), &[], Action::PanUp),
+            Binding::new(key("Down"), &[], Action::PanDown),
+            Binding::new(mouse("Middle"), &[], Action::DragCamera),
+            Binding::new(Trigger::WheelUp, &[Ctrl], Action::ZoomIn),
+            Binding::new(Trigger::WheelDown, &[Ctrl], Action::ZoomOut),
+        ];
+        Self::new(bindings)
+    }
+
+    fn new(bindings: Vec<Binding>) -> Self {
+        let inputs = bindings
+            .iter()
+            .map(|binding| Input::from_trigger(&binding.trigger))
+            .collect();
+        Self { bindings, inputs }
+    }
+
+    /// Loads the bindings from `path`. If the file doesn't exist yet it is created with the defaults.
+    /// If it can't be read we fall back to the defaults, so a typo doesn't lock you out of the app.
+    /// Actions the file doesn't mention get their default bindings, so files written by
+    /// older versions still reach everything that was added since
+    pub fn load_or_create(path: &Path) -> Self {
+        if !path.exists() {
+            let defaults = Self::defaults();
+            match defaults.save(path) {
+                Ok(()) => println!("Wrote default keybindings to {}", path.display()),
+                Err(error) => {
+                    println!("Failed to write keybindings to {}: {error}", path.display())
+                }
+            }
+            return defaults;
+        }
+        match Self::load(path) {
+            Ok(mut keybindings) => {
+                let added = keybindings.add_missing_defaults();
+                if !added.is_empty() {
+                    println!(
+                        "Added default bindings missing from {}: {}",
+                        path.display(),
+                        added.join(", ")
+                    );
+                }
+                keybindings
+            }
+            Err(error) => {
+                println!(
+                    "Failed to load keybindings from {}: {error}, using the defaults",
+                    path.display()
+                );
+                Self::defaults()
+            }
+        }
+    }
+
+    /// Adds the default bindings of every action that has no binding at all,
+    /// unless their chord is already taken. Returns the chords that were added
+    fn add_missing_defaults(&mut self) -> Vec<String> {
+        let mut added = vec![];
+        let mut bindings = self.bindings.clone();
+        for default in Self::defaults().bindings {
+            let bound = self
+                .bindings
+                .iter()
+                .any(|binding| binding.action == default.action);
+            let taken = bindings
+                .iter()
+                .any(|binding| binding.chord() == default.chord());
+            if bound {
+                continue;
+            }
+            if taken {
+                println!(
+                    "Keybindings: {} is already taken, so it isn't added for {:?}",
+                    default.chord(),
+                    default.action
+                );
+                continue;
+            }
+            added.push(format!("{} for {:?}", default.chord(), default.action));
+            bindings.push(default);
+        }
+        *self = Self::new(bindings);
+        added
+    }
+
+    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let bindings = ron::from_str(&text)?;
+        Ok(Self::new(bindings))
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = ron::ser::to_string_pretty(&self.bindings, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// The chord of the first binding for `action`, to show next to it in the ui
+    pub fn chord_for(&self, action: Action) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.action == action)
+            .map(Binding::chord)
+    }
+
+    /// Lists every problem with the bindings: key or button names we don't know,
+    /// and chords that are bound to more than one action
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        let mut chords: HashMap<String, Action> = HashMap::new();
+        for (binding, input) in self.bindings.iter().zip(&self.inputs) {
+            if let Input::Unknown = input {
+                problems.push(format!(
+                    "Unknown key or mouse button in {} for {:?}",
+                    binding.chord(),
+                    binding.action
+                ));
+            }
+            let chord = binding.chord();
+            match chords.get(&chord) {
+                Some(action) if *action != binding.action => problems.push(format!(
+                    "{chord} is bound to both {action:?} and {:?}",
+                    binding.action
+                )),
+                _ => {
+                    chords.insert(chord, binding.action);
+                }
+            }
+        }
+        problems
+    }
+
+    /// Returns the actions triggered this frame. The wheel is passed in as a number of
+    /// whole scroll steps, and `pointer_free` is false when the mouse is over the ui
+    pub fn triggered(&self, wheel_up: u32, wheel_down: u32, pointer_free: bool) -> Vec<Action> {
+        let mut actions = vec![];
+        for (binding, input) in self.bindings.iter().zip(&self.inputs) {
+            if !binding.modifiers_match() {
+                continue;
+            }
+            let continuous = binding.action.is_continuous();
+            let active = match *input {
+                Input::Key(key) if continuous => is_key_down(key),
+                Input::Key(key) => is_key_pressed(key),
+                Input::Mouse(button) if continuous => pointer_free && is_mouse_button_down(button),
+                Input::Mouse(button) => pointer_free && is_mouse_button_pressed(button),
+                _ => false,
+            };
+            let times = match input {
+                Input::WheelUp => wheel_up,
+                Input::WheelDown => wheel_down,
+                _ => active as u32,
+            };
+            for _ in 0..times {
+                actions.push(binding.action);
+            }
+        }
+        actions
+    }
+}
+
+fn mouse_button(name: &str) -> Option<MouseButton> {
+    match name {
+        "Left" => Some(MouseButton::Left),
+        "Middle" => Some(MouseButton::Middle),
+        "Right" => Some(MouseButton::Right),
+        _ => None,
+    }
+}
+
+/// Every key that can be bound. Keys are looked up by their KeyCode name
+const KEYS: [KeyCode; 85] = [
+    KeyCode::Space,
+    KeyCode::Apostrophe,
+    KeyCode::Comma,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Semicolon,
+    KeyCode::Equal,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::LeftBracket,
+    KeyCode::Backslash,
+    KeyCode::RightBracket,
+    KeyCode::GraveAccent,
+    KeyCode::Escape,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Right,
+    KeyCode::Left,
+    KeyCode::Down,
+    KeyCode::Up,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Kp0,
+    KeyCode::Kp1,
+    KeyCode::Kp2,
+    KeyCode::Kp3,
+    KeyCode::Kp4,
+    KeyCode::Kp5,
+    KeyCode::Kp6,
+    KeyCode::Kp7,
+    KeyCode::Kp8,
+    KeyCode::Kp9,
+    KeyCode::KpEnter,
+];
+
+fn key_code(name: &str) -> Option<KeyCode> {
+    KEYS.iter().find(|key| format!("{key:?}") == name).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chords(keybindings: &Keybindings) -> Vec<String> {
+        keybindings.bindings.iter().map(Binding::chord).collect()
+    }
+
+    #[test]
+    fn chords_list_modifiers_in_the_same_order() {
+        let binding = |modifiers: &[Modifier]| {
+            Binding::new(Trigger::Key("X".to_string()), modifiers, Action::Clear).chord()
+        };
+        use Modifier::*;
+        assert_eq!(binding(&[Ctrl, Shift]), "Shift+Ctrl+X");
+        assert_eq!(binding(&[Shift, Ctrl]), "Shift+Ctrl+X");
+        assert_eq!(binding(&[Alt, Ctrl, Shift]), "Shift+Ctrl+Alt+X");
+        assert_eq!(
+            Binding::new(Trigger::Mouse("Left".to_string()), &[], Action::Paint).chord(),
+            "MouseLeft"
+        );
+        assert_eq!(
+            Binding::new(Trigger::WheelDown, &[Alt], Action::ShrinkBrush).chord(),
+            "Alt+WheelDown"
+        );
+    }
+
+    #[test]
+    fn defaults_have_no_problems() {
+        assert_eq!(Keybindings::defaults().problems(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn defaults_keep_the_brush_size_off_alt() {
+        let defaults = Keybindings::defaults();
+        for binding in &defaults.bindings {
+            if matches!(binding.action, Action::GrowBrush | Action::ShrinkBrush) {
+                assert!(!binding.modifiers.contains(&Modifier::Alt));
+            }
+        }
+    }
+
+    #[test]
+    fn reports_unknown_keys_and_conflicts() {
+        let keybindings = Keybindings::new(vec![
+            Binding::new(Trigger::Key("Nope".to_string()), &[], Action::Quit),
+            Binding::new(Trigger::WheelUp, &[Modifier::Ctrl], Action::NextMaterial),
+            Binding::new(Trigger::WheelUp, &[Modifier::Ctrl], Action::GrowBrush),
+        ]);
+        assert_eq!(
+            keybindings.problems(),
+            [
+                "Unknown key or mouse button in Nope for Quit",
+                "Ctrl+WheelUp is bound to both NextMaterial and GrowBrush",
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_through_the_config_file() {
+        let path = std::env::temp_dir().join(format!("keybindings-{}.ron", std::process::id()));
+        let defaults = Keybindings::defaults();
+        defaults.save(&path).unwrap();
+        let loaded = Keybindings::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(chords(&loaded), chords(&defaults));
+        let actions = |keybindings: &Keybindings| -> Vec<Action> {
+            keybindings
+                .bindings
+                .iter()
+                .map(|binding| binding.action)
+                .collect()
+        };
+        assert_eq!(actions(&loaded), actions(&defaults));
+    }
+
+    #[test]
+    fn parses_handwritten_bindings() {
+        let bindings: Vec<Binding> = ron::from_str(
+            "[(trigger: Key(\"Q\"), action: Quit), \
+             (trigger: WheelUp, modifiers: [Ctrl, Shift], action: GrowBrush)]",
+        )
+        .unwrap();
+        let keybindings = Keybindings::new(bindings);
+        assert_eq!(chords(&keybindings), ["Q", "Shift+Ctrl+WheelUp"]);
+        assert!(keybindings.problems().is_empty());
+    }
+
+    #[test]
+    fn adds_defaults_for_unbound_actions_only() {
+        let mut keybindings = Keybindings::new(vec![
+            Binding::new(Trigger::Key("Q".to_string()), &[], Action::Quit),
+            // Takes the default chord of Clear
+            Binding::new(Trigger::Key("C".to_string()), &[], Action::TogglePause),
+        ]);
+        let added = keybindings.add_missing_defaults();
+
+        assert!(added.iter().all(|added| !added.ends_with("for Quit")));
+        assert!(!added.contains(&"C for Clear".to_string()));
+        assert!(added.contains(&"Period for Step".to_string()));
+        let clear = keybindings
+            .bindings
+            .iter()
+            .filter(|binding| binding.action == Action::Clear)
+            .count();
+        assert_eq!(clear, 0);
+        assert!(keybindings.problems().is_empty());
+    }
+}
@@ -1,47 +1,171 @@
 use macroquad::{
-    main,
     prelude::*,
-    ui::{hash, root_ui, widgets},
+    ui::{Ui, hash, root_ui, widgets},
 };
 mod app;
 mod brush;
+mod camera;
+mod command;
+mod console;
+mod debug;
+mod headless;
+mod history;
+mod image_io;
+mod keybindings;
 mod pixel;
 mod pixel_grid;
+mod plugin;
+mod prefab;
+mod profiler;
+mod recorder;
+mod replay;
+mod rigid_body;
+mod rules;
+mod save;
+mod scenario;
+mod scripting;
+mod selection;
+mod settings;
+mod stats;
 use app::App;
+use brush::{BrushMode, BrushType};
+use camera::ScalingMode;
+use command::AppCommand;
+use debug::Overlays;
+use keybindings::Action;
+use pixel::{Category, MATERIAL_COUNT, PixelType};
 use pixel_grid::ChunkPosition;
+use profiler::{Phase, Profiler};
+use recorder::CaptureFormat;
+use settings::Settings;
+use stats::Stats;
+use std::{path::PathBuf, sync::OnceLock, time::Instant};
 
-pub fn window_settings() -> Conf {
+pub fn window_settings(settings: &Settings) -> Conf {
     Conf {
         window_title: String::from("Sandbox"),
-        window_width: 1280,
-        window_height: 720,
+        window_width: settings.window_width as i32,
+        window_height: settings.window_height as i32,
         ..Default::default()
     }
 }
 
-const CHUNK_SIZE: (usize, usize) = (160, 90);
-const RENDER_SIZE: (u32, u32) = (240, 125);
-
-#[main(window_settings)]
-async fn main() {
-    let conf = window_settings();
-    let initial_width = conf.window_width;
-    let initial_height = conf.window_height;
-    let width_ratio = initial_width as f32 / RENDER_SIZE.0 as f32;
-    let height_ratio = initial_height as f32 / RENDER_SIZE.1 as f32;
-    let mut app = App::new((width_ratio, height_ratio));
+/// Where the config file `file_name` lives: in the platform's config directory,
+/// or the working directory if there is none
+pub fn config_path(file_name: &str) -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join("sandbox"))
+        .unwrap_or_default()
+        .join(file_name)
+}
+
+/// Draws a line chart of `lines` in the window, all scaled to the highest value, which is returned
+fn draw_graph(ui: &mut Ui, size: Vec2, lines: &[(Color, Vec<f32>)]) -> f32 {
+    let highest = lines
+        .iter()
+        .flat_map(|(_, values)| values)
+        .fold(0.001_f32, |highest, value| highest.max(*value));
+    let mut canvas = ui.canvas();
+    let corner = canvas.request_space(size);
+    canvas.rect(Rect::new(corner.x, corner.y, size.x, size.y), GRAY, None);
+    for (color, values) in lines {
+        let step = size.x / values.len().max(2) as f32;
+        let point = |index: usize, value: f32| {
+            corner + vec2(index as f32 * step, size.y * (1.0 - value / highest))
+        };
+        for (index, pair) in values.windows(2).enumerate() {
+            canvas.line(point(index, pair[0]), point(index + 1, pair[1]), *color);
+        }
+    }
+    highest
+}
+
+// The palette gets a search box and favourites once there are more materials than this
+const MATERIALS_BEFORE_SEARCH: usize = 12;
+// How many lines of output the console window shows
+const CONSOLE_LINES: usize = 12;
+
+// The chunk size comes from the settings, and is set once at startup before any chunk is created
+static CHUNK_SIZE: OnceLock<(usize, usize)> = OnceLock::new();
+
+pub fn chunk_size() -> (usize, usize) {
+    *CHUNK_SIZE.get().expect("Chunk size is not set yet")
+}
+
+fn main() {
+    // `sandbox --headless ...` simulates without opening a window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let headless = args.first().is_some_and(|arg| arg == "--headless");
+    // Headless runs only read the settings, so scripted runs don't write to the config directory
+    let settings_path = config_path("settings.ron");
+    let settings = if headless {
+        Settings::load_or_default(&settings_path)
+    } else {
+        Settings::load_or_create(&settings_path)
+    };
+    CHUNK_SIZE
+        .set((
+            settings.chunk_size.0.max(1) as usize,
+            settings.chunk_size.1.max(1) as usize,
+        ))
+        .expect("Chunk size was already set");
+
+    if headless {
+        if let Err(error) = headless::run(&args[1..]) {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    macroquad::Window::from_config(window_settings(&settings), run(settings));
+}
+
+async fn run(settings: Settings) {
+    let mut app = App::new(settings);
+    let mut show_settings = false;
+    let mut replay_path = String::from("replay.ron");
+    let mut world_path = String::from("world.ron");
+    let mut replace_material = PixelType::Sand;
+    let mut prefab_name = String::new();
+    let mut image_path = String::from("map.png");
+    let mut export_path = String::from("export.png");
+    let mut export_scale = 1u32;
+    let mut capture_path = String::from("capture.gif");
+    let mut capture_format = 0;
+    let mut capture_every = 1u32;
+    let mut capture_scale = 1u32;
+    let mut capture_fps = 30u32;
+    let mut scenario_path = String::from("scenario.ron");
+    let mut material_search = String::new();
+    let mut overlays = Overlays::default();
+    let mut profiler = Profiler::default();
+    let mut show_profiler = false;
+    let mut profile_path = String::from("profile.csv");
+    let mut stats = Stats::default();
+    let mut show_stats = false;
+    let mut stats_path = String::from("stats.csv");
+    // A little square in the color of every material, for the palette. Scripts can add materials
+    // and change their colors while the app runs, so they are made when they are first needed
+    let mut swatches: Vec<Option<(Color, Texture2D)>> = vec![None; MATERIAL_COUNT];
     while app.running() {
+        let start = Instant::now();
         app.handle_input();
+        profiler.record(Phase::Input, start.elapsed());
+
+        let draw_start = Instant::now();
         app.start_drawing();
         clear_background(SKYBLUE);
 
-        app.chunks().draw();
+        let texture_cost = app.chunks().draw();
+        profiler.record(Phase::Texture, texture_cost);
+        overlays.draw(app.chunks(), app.camera().zoom());
+        app.draw_previews();
 
-        widgets::Window::new(hash!(), vec2(0.0, 0.0), vec2(300.0, 300.0))
+        widgets::Window::new(hash!(), vec2(0.0, 0.0), vec2(300.0, 360.0))
             .label("Info")
             .movable(true)
             .titlebar(true)
-            .ui(&mut *root_ui(), |ui| {
+            .ui(&mut root_ui(), |ui| {
                 ui.label(None, format!("FPS: {}", get_fps()).as_str());
                 ui.label(
                     None,
@@ -49,7 +173,18 @@ async fn main() {
                 );
                 ui.separator();
                 if ui.button(None, "Reset pixelgrid") {
-                    app.chunks_mut().clear();
+                    app.execute(AppCommand::Clear);
+                }
+                if ui.button(None, "Settings") {
+                    show_settings = !show_settings;
+                }
+                ui.same_line(0.0);
+                if ui.button(None, "Profiler") {
+                    show_profiler = !show_profiler;
+                }
+                ui.same_line(0.0);
+                if ui.button(None, "Stats") {
+                    show_stats = !show_stats;
                 }
                 ui.label(
                     None,
@@ -61,6 +196,54 @@ async fn main() {
                     format!("Selected brush type: {}", app.brush().brush_type().as_str()).as_str(),
                 );
                 ui.label(None, format!("Brush size: {}", app.brush().size()).as_str());
+                let brush_mode = app.brush().brush_mode();
+                let mut mode = brush_mode.index();
+                ui.combo_box(hash!(), "Brush mode", &BrushMode::NAMES, &mut mode);
+                // Remember the material to replace while switching between modes
+                let mut replace = match brush_mode {
+                    BrushMode::ReplaceOnly(pixel_type) => pixel_type,
+                    _ => replace_material,
+                };
+                if mode == 1 {
+                    let all = PixelType::all();
+                    let materials: Vec<&str> =
+                        all.iter().map(|pixel_type| pixel_type.get()).collect();
+                    let mut material = all
+                        .iter()
+                        .position(|pixel_type| *pixel_type == replace)
+                        .unwrap_or(0);
+                    ui.combo_box(hash!(), "Replace", &materials, &mut material);
+                    replace = all[material];
+                }
+                replace_material = replace;
+                let new_mode = match mode {
+                    1 => BrushMode::ReplaceOnly(replace),
+                    2 => BrushMode::EmptyOnly,
+                    _ => BrushMode::Normal,
+                };
+                if new_mode != brush_mode {
+                    app.execute(AppCommand::SetBrushMode(new_mode));
+                }
+                if app.brush().brush_type() == BrushType::Spray {
+                    let mut density = app.brush().spray_density();
+                    ui.slider(hash!(), "Spray density", 0.01..1.0, &mut density);
+                    if density != app.brush().spray_density() {
+                        app.execute(AppCommand::SetSprayDensity(density));
+                    }
+                }
+                if let Some(clipboard) = app.clipboard() {
+                    let pasting = if app.pasting() { " (pasting)" } else { "" };
+                    ui.label(
+                        None,
+                        format!(
+                            "Clipboard: {}x{}{pasting}",
+                            clipboard.width(),
+                            clipboard.height()
+                        )
+                        .as_str(),
+                    );
+                }
+                ui.label(None, format!("Zoom: {:.2}", app.camera().zoom()).as_str());
                 ui.label(
                     None,
                     format!("Mouse screen position: {:?}", mouse_position()).as_str(),
@@ -80,10 +263,676 @@ async fn main() {
                 );
             });
 
+        widgets::Window::new(
+            hash!(),
+            vec2(screen_width() - 650.0, 0.0),
+            vec2(240.0, 400.0),
+        )
+        .label("Materials")
+        .movable(true)
+        .titlebar(true)
+        .ui(&mut root_ui(), |ui| {
+            // Searching and favourites only pay off once there are more materials than fit on screen
+            let all = PixelType::all();
+            let many_materials = all.len() > MATERIALS_BEFORE_SEARCH;
+            if many_materials {
+                ui.input_text(hash!(), "Search", &mut material_search);
+            }
+            let search = material_search.to_lowercase();
+            let mut clicked = None;
+            let mut toggled_favourite = None;
+            let favourites = app.settings_mut().favourite_materials.clone();
+            let mut sections: Vec<(&str, Vec<PixelType>)> = vec![];
+            if many_materials && !favourites.is_empty() {
+                sections.push(("Favourites", favourites.clone()));
+            }
+            for category in Category::ALL {
+                let materials = all
+                    .iter()
+                    .copied()
+                    .filter(|pixel_type| pixel_type.category() == category)
+                    .collect();
+                sections.push((category.as_str(), materials));
+            }
+            for (section, materials) in sections {
+                let materials: Vec<PixelType> = materials
+                    .into_iter()
+                    .filter(|pixel_type| pixel_type.get().to_lowercase().contains(&search))
+                    .collect();
+                if materials.is_empty() {
+                    continue;
+                }
+                ui.label(None, section);
+                for pixel_type in materials {
+                    let color = pixel_type.to_color();
+                    let swatch = &mut swatches[pixel_type.index()];
+                    if swatch.as_ref().is_none_or(|(drawn, _)| *drawn != color) {
+                        let rgba: [u8; 4] = color.into();
+                        *swatch = Some((color, Texture2D::from_rgba8(1, 1, &rgba)));
+                    }
+                    let texture = swatch.as_ref().map(|(_, texture)| texture.clone());
+                    if ui.texture(texture.unwrap(), 16.0, 16.0) {
+                        clicked = Some(pixel_type);
+                    }
+                    ui.same_line(24.0);
+                    let selected = if app.brush().pixel_type() == pixel_type {
+                        "> "
+                    } else {
+                        ""
+                    };
+                    if ui.button(None, format!("{selected}{}", pixel_type.get()).as_str()) {
+                        clicked = Some(pixel_type);
+                    }
+                    if let Some(chord) = app
+                        .keybindings()
+                        .chord_for(Action::SelectMaterial(pixel_type))
+                    {
+                        ui.same_line(130.0);
+                        ui.label(None, &chord);
+                    }
+                    if many_materials {
+                        ui.same_line(190.0);
+                        let star = if favourites.contains(&pixel_type) {
+                            "*"
+                        } else {
+                            "+"
+                        };
+                        if ui.button(None, star) {
+                            toggled_favourite = Some(pixel_type);
+                        }
+                    }
+                }
+            }
+            if let Some(pixel_type) = clicked {
+                app.execute(AppCommand::SelectMaterial(pixel_type));
+            }
+            if let Some(pixel_type) = toggled_favourite {
+                let favourites = &mut app.settings_mut().favourite_materials;
+                match favourites.iter().position(|other| *other == pixel_type) {
+                    Some(index) => {
+                        favourites.remove(index);
+                    }
+                    None => favourites.push(pixel_type),
+                }
+            }
+        });
+
+        widgets::Window::new(
+            hash!(),
+            vec2(screen_width() - 650.0, 410.0),
+            vec2(240.0, 200.0),
+        )
+        .label("Inspector")
+        .movable(true)
+        .titlebar(true)
+        .ui(&mut root_ui(), |ui| {
+            let cell = app.inspected_cell();
+            let position = ChunkPosition::from_world_position(cell);
+            if app.is_inspector_pinned() {
+                ui.label(None, format!("Pinned at {}, {}", cell.x, cell.y).as_str());
+                if ui.button(None, "Unpin") {
+                    app.execute(AppCommand::PinInspector(None));
+                }
+            } else {
+                ui.label(
+                    None,
+                    format!("Under the mouse: {}, {}", cell.x, cell.y).as_str(),
+                );
+            }
+            ui.separator();
+            let Some(chunk) = app.chunks().chunk(position.chunk_key) else {
+                ui.label(None, "Outside of the loaded chunks");
+                return;
+            };
+            let material = app.chunks().get_pixel(cell).unwrap_or(PixelType::Air);
+            ui.label(
+                None,
+                format!(
+                    "Material: {} ({})",
+                    material.get(),
+                    material.category().as_str()
+                )
+                .as_str(),
+            );
+            ui.label(
+                None,
+                format!(
+                    "Chunk {:?}, local {:?}",
+                    position.chunk_key, position.chunk_coordinate
+                )
+                .as_str(),
+            );
+            let status = if chunk.is_active() { "yes" } else { "no" };
+            ui.label(None, format!("Chunk active last tick: {status}").as_str());
+            ui.label(
+                None,
+                format!("Chunk pixels: {}", chunk.pixel_count()).as_str(),
+            );
+        });
+
+        widgets::Window::new(hash!(), vec2(310.0, 430.0), vec2(220.0, 250.0))
+            .label("Debug")
+            .movable(true)
+            .titlebar(true)
+            .ui(&mut root_ui(), |ui| {
+                ui.checkbox(hash!(), "Chunk borders", &mut overlays.chunk_borders);
+                ui.checkbox(hash!(), "Moved cells", &mut overlays.moved_cells);
+                ui.checkbox(
+                    hash!(),
+                    "Cross-chunk moves",
+                    &mut overlays.cross_chunk_moves,
+                );
+                ui.checkbox(hash!(), "Update cost heat map", &mut overlays.heat_map);
+                ui.checkbox(
+                    hash!(),
+                    &format!("Rigid bodies ({})", app.chunks().bodies().len()),
+                    &mut overlays.rigid_bodies,
+                );
+                ui.separator();
+                let scripted: Vec<&str> = app
+                    .chunks()
+                    .scripts()
+                    .materials()
+                    .map(|pixel_type| pixel_type.get())
+                    .collect();
+                ui.label(None, &format!("Scripted: {}", scripted.join(", ")));
+                if ui.button(None, "Reload scripts") {
+                    app.execute(AppCommand::ReloadScripts);
+                }
+                let rules = app.chunks().rules().names().join(", ");
+                ui.label(None, &format!("Rules: {rules}"));
+                if ui.button(None, "Reload rules") {
+                    app.execute(AppCommand::ReloadRules);
+                }
+            });
+
+        widgets::Window::new(hash!(), vec2(310.0, 690.0), vec2(220.0, 160.0))
+            .label("Plugins")
+            .movable(true)
+            .titlebar(true)
+            .ui(&mut root_ui(), |ui| {
+                let plugins = app.chunks().plugins();
+                let materials: Vec<&str> = plugins
+                    .materials()
+                    .map(|pixel_type| pixel_type.get())
+                    .collect();
+                ui.label(None, &format!("Materials: {}", materials.join(", ")));
+                // Clicking a brush tool picks it, clicking it again goes back to the brush
+                let mut picked = None;
+                for brush in plugins.brushes() {
+                    let selected = app.plugin_brush() == Some(brush);
+                    let label = match selected {
+                        true => format!("[{brush}]"),
+                        false => brush.to_string(),
+                    };
+                    if ui.button(None, label.as_str()) {
+                        picked = Some((!selected).then(|| brush.to_string()));
+                    }
+                }
+                if let Some(name) = picked {
+                    app.execute(AppCommand::SelectPluginBrush(name));
+                }
+                if ui.button(None, "Reload plugins") {
+                    app.execute(AppCommand::ReloadPlugins);
+                }
+            });
+
+        widgets::Window::new(hash!(), vec2(0.0, 370.0), vec2(300.0, 300.0))
+            .label("Prefabs")
+            .movable(true)
+            .titlebar(true)
+            .ui(&mut root_ui(), |ui| {
+                ui.input_text(hash!(), "Name", &mut prefab_name);
+                if ui.button(None, "Save selection") {
+                    app.execute(AppCommand::SavePrefab(prefab_name.clone()));
+                }
+                ui.same_line(0.0);
+                if ui.button(None, "Reload") {
+                    app.execute(AppCommand::ReloadPrefabs);
+                }
+                ui.separator();
+                let mut clicked = None;
+                for prefab in app.prefabs().prefabs() {
+                    // Fit the thumbnail in a 64 by 64 square, keeping its shape
+                    let size = prefab.thumbnail().size();
+                    let scale = 64.0 / size.x.max(size.y);
+                    if ui.texture(prefab.thumbnail().clone(), size.x * scale, size.y * scale) {
+                        clicked = Some(prefab.name().to_string());
+                    }
+                    ui.same_line(80.0);
+                    ui.label(None, prefab.title());
+                    let info = prefab.info();
+                    if !info.description.is_empty() {
+                        ui.label(None, &info.description);
+                    }
+                    if !info.tags.is_empty() {
+                        ui.label(None, &format!("Tags: {}", info.tags.join(", ")));
+                    }
+                }
+                if let Some(name) = clicked {
+                    app.execute(AppCommand::PlacePrefab(name));
+                }
+            });
+
+        widgets::Window::new(
+            hash!(),
+            vec2(screen_width() - 400.0, 0.0),
+            vec2(400.0, 150.0),
+        )
+        .label("Timeline")
+        .movable(true)
+        .titlebar(true)
+        .ui(&mut root_ui(), |ui| {
+            let mut paused = app.paused();
+            ui.checkbox(hash!(), "Paused", &mut paused);
+            if paused != app.paused() {
+                app.execute(AppCommand::Pause(paused));
+            }
+            if paused && ui.button(None, "Step") {
+                app.execute(AppCommand::Step);
+            }
+            ui.label(None, format!("Tick: {}", app.chunks().tick()).as_str());
+
+            let snapshots = app.timeline().len();
+            if snapshots > 1 {
+                // The slider sits on the last snapshot while we are live
+                let current = app.timeline().cursor().unwrap_or(snapshots - 1);
+                let mut position = current as f32;
+                ui.slider(
+                    hash!(),
+                    "Snapshot",
+                    0.0..(snapshots - 1) as f32,
+                    &mut position,
+                );
+                let position = position.round() as usize;
+                if position != current {
+                    app.execute(AppCommand::Scrub(position));
+                }
+            }
+            if let Some(cursor) = app.timeline().cursor() {
+                let snapshot_tick = app.timeline().get(cursor).map_or(0, |s| s.tick());
+                ui.label(None, format!("Viewing tick: {snapshot_tick}").as_str());
+                if ui.button(None, "Play from here") {
+                    app.execute(AppCommand::Branch);
+                }
+                if ui.button(None, "Back to live") {
+                    app.execute(AppCommand::BackToLive);
+                }
+            }
+        });
+
+        widgets::Window::new(
+            hash!(),
+            vec2(screen_width() - 400.0, 160.0),
+            vec2(400.0, 300.0),
+        )
+        .label("Replay & saves")
+        .movable(true)
+        .titlebar(true)
+        .ui(&mut root_ui(), |ui| {
+            ui.input_text(hash!(), "File", &mut replay_path);
+            if app.is_recording() {
+                if ui.button(None, "Stop recording") {
+                    app.execute(AppCommand::StopRecording(replay_path.clone()));
+                }
+            } else if let Some(playback) = app.playback() {
+                let (played, total) = playback.progress();
+                ui.label(None, format!("Playing: {played}/{total} commands").as_str());
+                if ui.button(None, "Stop playback") {
+                    app.execute(AppCommand::StopPlayback);
+                }
+            } else {
+                if ui.button(None, "Record (restarts world)") {
+                    app.execute(AppCommand::StartRecording);
+                }
+                if ui.button(None, "Play") {
+                    app.execute(AppCommand::PlayReplay(replay_path.clone()));
+                }
+            }
+            ui.separator();
+            ui.input_text(hash!(), "World", &mut world_path);
+            if ui.button(None, "Save world") {
+                app.execute(AppCommand::Save(world_path.clone()));
+            }
+            if ui.button(None, "Load world") {
+                app.execute(AppCommand::Load(world_path.clone()));
+            }
+            ui.separator();
+            ui.input_text(hash!(), "Image", &mut image_path);
+            if ui.button(None, "Import image") {
+                app.execute(AppCommand::ImportImage(image_path.clone()));
+            }
+            ui.input_text(hash!(), "Export", &mut export_path);
+            ui.drag(hash!(), "Scale", (1, 16), &mut export_scale);
+            if ui.button(None, "Export selection or world") {
+                app.execute(AppCommand::ExportImage {
+                    path: export_path.clone(),
+                    scale: export_scale,
+                });
+            }
+        });
+
+        widgets::Window::new(
+            hash!(),
+            vec2(screen_width() - 400.0, 470.0),
+            vec2(400.0, 200.0),
+        )
+        .label("Capture")
+        .movable(true)
+        .titlebar(true)
+        .ui(&mut root_ui(), |ui| {
+            if let Some(capture) = app.capture() {
+                ui.label(
+                    None,
+                    format!(
+                        "Capturing {}: {} frames",
+                        capture.format().as_str(),
+                        capture.frames()
+                    )
+                    .as_str(),
+                );
+                if ui.button(None, "Stop capture") {
+                    app.execute(AppCommand::StopCapture);
+                }
+            } else {
+                ui.input_text(hash!(), "File", &mut capture_path);
+                let formats = CaptureFormat::ALL.map(|format| format.as_str());
+                ui.combo_box(hash!(), "Format", &formats, &mut capture_format);
+                ui.drag(hash!(), "Every N ticks", (1, 100), &mut capture_every);
+                ui.drag(hash!(), "Scale", (1, 16), &mut capture_scale);
+                ui.drag(hash!(), "Frames per second", (1, 100), &mut capture_fps);
+                if ui.button(None, "Capture selection or view") {
+                    app.execute(AppCommand::StartCapture {
+                        path: capture_path.clone(),
+                        format: CaptureFormat::ALL[capture_format],
+                        every: capture_every as u64,
+                        scale: capture_scale,
+                        fps: capture_fps,
+                    });
+                }
+            }
+        });
+
+        widgets::Window::new(
+            hash!(),
+            vec2(screen_width() - 400.0, 680.0),
+            vec2(400.0, 200.0),
+        )
+        .label("Scenario")
+        .movable(true)
+        .titlebar(true)
+        .ui(&mut root_ui(), |ui| {
+            ui.input_text(hash!(), "File", &mut scenario_path);
+            if ui.button(None, "Run") {
+                app.execute(AppCommand::RunScenario(scenario_path.clone()));
+            }
+            ui.same_line(0.0);
+            if ui.button(None, "Stop") {
+                app.execute(AppCommand::StopScenario);
+            }
+            if let Some(scenario) = app.scenario() {
+                let (tick, length) = scenario.progress();
+                let failures = scenario.failures();
+                let passed = scenario.outcomes().len() - failures;
+                ui.label(
+                    None,
+                    format!("Tick {tick} of {length}: {passed} passed, {failures} failed").as_str(),
+                );
+                for outcome in scenario.outcomes() {
+                    let result = if outcome.passed { "passed" } else { "FAILED" };
+                    ui.label(
+                        None,
+                        format!("{}: {result} {}", outcome.tick, outcome.description).as_str(),
+                    );
+                }
+            }
+        });
+
+        if show_settings {
+            widgets::Window::new(hash!(), vec2(310.0, 0.0), vec2(360.0, 420.0))
+                .label("Settings")
+                .movable(true)
+                .titlebar(true)
+                .ui(&mut root_ui(), |ui| {
+                    let settings = app.settings_mut();
+                    ui.label(None, "Applied after a restart");
+                    ui.drag(
+                        hash!(),
+                        "Window width",
+                        (320, 7680),
+                        &mut settings.window_width,
+                    );
+                    ui.drag(
+                        hash!(),
+                        "Window height",
+                        (180, 4320),
+                        &mut settings.window_height,
+                    );
+                    ui.drag(
+                        hash!(),
+                        "Chunk width",
+                        (8, 1024),
+                        &mut settings.chunk_size.0,
+                    );
+                    ui.drag(
+                        hash!(),
+                        "Chunk height",
+                        (8, 1024),
+                        &mut settings.chunk_size.1,
+                    );
+                    ui.drag(
+                        hash!(),
+                        "Snapshot interval",
+                        (1, 1000),
+                        &mut settings.snapshot_interval,
+                    );
+                    ui.drag(
+                        hash!(),
+                        "Snapshot capacity",
+                        (1, 10000),
+                        &mut settings.snapshot_capacity,
+                    );
+                    let all = PixelType::all();
+                    let materials: Vec<&str> =
+                        all.iter().map(|pixel_type| pixel_type.get()).collect();
+                    let mut material = all
+                        .iter()
+                        .position(|pixel_type| *pixel_type == settings.default_material)
+                        .unwrap_or(0);
+                    ui.combo_box(hash!(), "Default material", &materials, &mut material);
+                    settings.default_material = all[material];
+                    let brushes = BrushType::ALL.map(|brush_type| brush_type.as_str());
+                    let mut brush = BrushType::ALL
+                        .iter()
+                        .position(|brush_type| *brush_type == settings.default_brush)
+                        .unwrap_or(0);
+                    ui.combo_box(hash!(), "Default brush", &brushes, &mut brush);
+                    settings.default_brush = BrushType::ALL[brush];
+                    ui.drag(
+                        hash!(),
+                        "Default brush size",
+                        (1.0, 100.0),
+                        &mut settings.default_brush_size,
+                    );
+                    ui.slider(
+                        hash!(),
+                        "Default spray density",
+                        0.01..1.0,
+                        &mut settings.default_spray_density,
+                    );
+                    ui.separator();
+                    ui.label(None, "Applied right away");
+                    ui.drag(
+                        hash!(),
+                        "Render width",
+                        (16, 3840),
+                        &mut settings.render_size.0,
+                    );
+                    ui.drag(
+                        hash!(),
+                        "Render height",
+                        (16, 2160),
+                        &mut settings.render_size.1,
+                    );
+                    let modes = ScalingMode::ALL.map(|mode| mode.as_str());
+                    let mut mode = ScalingMode::ALL
+                        .iter()
+                        .position(|mode| *mode == settings.scaling_mode)
+                        .unwrap_or(0);
+                    ui.combo_box(hash!(), "Scaling", &modes, &mut mode);
+                    settings.scaling_mode = ScalingMode::ALL[mode];
+                    ui.drag(
+                        hash!(),
+                        "Scroll up threshold",
+                        (1.0, 1000.0),
+                        &mut settings.scroll_up_threshold,
+                    );
+                    ui.drag(
+                        hash!(),
+                        "Scroll down threshold",
+                        (-1000.0, -1.0),
+                        &mut settings.scroll_down_threshold,
+                    );
+                    if ui.button(None, "Close") {
+                        show_settings = false;
+                    }
+                });
+        }
+
+        if show_profiler {
+            widgets::Window::new(hash!(), vec2(540.0, 430.0), vec2(420.0, 320.0))
+                .label("Profiler")
+                .movable(true)
+                .titlebar(true)
+                .ui(&mut root_ui(), |ui| {
+                    for phase in Phase::ALL {
+                        ui.label(
+                            None,
+                            format!("{}: {:.3} ms", phase.as_str(), profiler.average(phase))
+                                .as_str(),
+                        );
+                    }
+                    // One line per phase over the last frames
+                    let history = profiler.history();
+                    let lines: Vec<(Color, Vec<f32>)> = Phase::ALL
+                        .iter()
+                        .map(|phase| {
+                            let timings = history.iter().map(|frame| frame[*phase as usize]);
+                            (phase.color(), timings.collect())
+                        })
+                        .collect();
+                    let highest = draw_graph(ui, vec2(400.0, 80.0), &lines);
+                    ui.label(None, format!("Graph top: {highest:.3} ms").as_str());
+                    ui.separator();
+                    ui.label(None, "Slowest chunks last tick:");
+                    for (key, cost) in profiler.slowest_chunks() {
+                        ui.label(
+                            None,
+                            format!("{key:?}: {:.3} ms", cost.as_secs_f32() * 1000.0).as_str(),
+                        );
+                    }
+                    ui.separator();
+                    ui.input_text(hash!(), "CSV", &mut profile_path);
+                    if ui.button(None, "Export CSV") {
+                        match profiler.export_csv(&profile_path) {
+                            Ok(()) => println!("Exported the profile to {profile_path}"),
+                            Err(error) => {
+                                println!("Failed to export the profile to {profile_path}: {error}")
+                            }
+                        }
+                    }
+                });
+        }
+
+        if show_stats {
+            widgets::Window::new(hash!(), vec2(540.0, 100.0), vec2(420.0, 320.0))
+                .label("Stats")
+                .movable(true)
+                .titlebar(true)
+                .ui(&mut root_ui(), |ui| {
+                    let history = stats.history();
+                    if let Some(last) = history.back() {
+                        ui.label(None, format!("Tick {}", last.tick).as_str());
+                        for pixel_type in PixelType::all() {
+                            ui.label(
+                                None,
+                                format!(
+                                    "{}: {}",
+                                    pixel_type.get(),
+                                    last.counts[pixel_type.index()]
+                                )
+                                .as_str(),
+                            );
+                        }
+                        ui.label(
+                            None,
+                            format!("Moving: {}, settled: {}", last.moving, last.settled())
+                                .as_str(),
+                        );
+                    }
+                    // One line per material, Air would dwarf the rest so it is left out.
+                    // Moving pixels are the white line
+                    let mut lines: Vec<(Color, Vec<f32>)> = PixelType::all()
+                        .iter()
+                        .filter(|pixel_type| **pixel_type != PixelType::Air)
+                        .map(|pixel_type| {
+                            let counts = history
+                                .iter()
+                                .map(|sample| sample.counts[pixel_type.index()] as f32);
+                            (pixel_type.to_color(), counts.collect())
+                        })
+                        .collect();
+                    lines.push((
+                        WHITE,
+                        history.iter().map(|sample| sample.moving as f32).collect(),
+                    ));
+                    let highest = draw_graph(ui, vec2(400.0, 80.0), &lines);
+                    ui.label(None, format!("Graph top: {highest:.0} pixels").as_str());
+                    ui.input_text(hash!(), "CSV", &mut stats_path);
+                    if ui.button(None, "Export CSV") {
+                        match stats.export_csv(&stats_path) {
+                            Ok(()) => println!("Exported the stats to {stats_path}"),
+                            Err(error) => {
+                                println!("Failed to export the stats to {stats_path}: {error}")
+                            }
+                        }
+                    }
+                });
+        }
+
+        if app.console().is_open() {
+            widgets::Window::new(hash!(), vec2(0.0, 0.0), vec2(screen_width(), 240.0))
+                .label("Console")
+                .titlebar(true)
+                .ui(&mut root_ui(), |ui| {
+                    // Only the lines that fit, newest at the bottom
+                    let output = app.console().output();
+                    for line in output
+                        .iter()
+                        .skip(output.len().saturating_sub(CONSOLE_LINES))
+                    {
+                        ui.label(None, line);
+                    }
+                    ui.separator();
+                    ui.label(None, &format!("> {}_", app.console().input()));
+                });
+        }
+
         app.stop_drawing();
+        profiler.record(
+            Phase::Draw,
+            draw_start.elapsed().saturating_sub(texture_cost),
+        );
 
-        app.chunks_mut().update();
+        let tick = app.chunks().tick();
+        app.update();
+        if app.chunks().tick() != tick {
+            profiler.record_tick(app.chunks());
+            stats.record_tick(app.chunks());
+        }
+        profiler.end_frame();
 
         next_frame().await;
     }
+    app.save_settings();
 }
@@ -1,7 +1,69 @@
-use crate::pixel_grid::{Chunk, GridMovement};
+use crate::{
+    pixel_grid::{Chunk, GridMovement},
+    plugin::PluginHost,
+    scripting::MaterialScripts,
+};
 use macroquad::{prelude::*, rand::RandGenerator};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 
+/// The groups materials are listed in on the palette
 #[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Category {
+    Powder,
+    Liquid,
+    Gas,
+    Solid,
+    Special,
+}
+impl Category {
+    /// Looks a category up by its singular name, like "powder", ignoring case
+    pub fn from_name(name: &str) -> Option<Category> {
+        Self::ALL
+            .into_iter()
+            .find(|category| format!("{category:?}").eq_ignore_ascii_case(name))
+    }
+
+    pub const ALL: [Category; 5] = [
+        Category::Powder,
+        Category::Liquid,
+        Category::Gas,
+        Category::Solid,
+        Category::Special,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Powder => "Powders",
+            Category::Liquid => "Liquids",
+            Category::Gas => "Gases",
+            Category::Solid => "Solids",
+            Category::Special => "Special",
+        }
+    }
+}
+
+/// How many materials scripts can add on top of the built-in ones
+pub const MAX_CUSTOM: usize = 64;
+/// How many materials there can be at most. Tables with a slot per material are this big
+pub const MATERIAL_COUNT: usize = PixelType::BUILT_IN.len() + MAX_CUSTOM;
+
+/// A material that was added while the app runs
+struct CustomMaterial {
+    // Leaked once per name, so names can be handed out like the built-in ones
+    name: &'static str,
+    color: Color,
+    category: Category,
+}
+
+thread_local! {
+    // Custom materials never go away, so the cells, snapshots and replays that hold one
+    // stay valid when the scripts are reloaded
+    static CUSTOM_MATERIALS: RefCell<Vec<CustomMaterial>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(try_from = "MaterialName", into = "MaterialName")]
 pub enum PixelType {
     Sand,
     Water,
@@ -9,30 +71,123 @@ pub enum PixelType {
     Dirt,
     Stone,
     Grass,
+    /// A material added by a script, see `PixelType::define`
+    Custom(u8),
 }
 impl PixelType {
-    pub fn next(&mut self) {
-        match *self {
-            PixelType::Sand => *self = PixelType::Water,
-            PixelType::Water => *self = PixelType::Dirt,
-            PixelType::Dirt => *self = PixelType::Stone,
-            PixelType::Stone => *self = PixelType::Grass,
-            PixelType::Grass => *self = PixelType::Sand,
-            _ => (),
+    pub const BUILT_IN: [PixelType; 6] = [
+        PixelType::Sand,
+        PixelType::Water,
+        PixelType::Air,
+        PixelType::Dirt,
+        PixelType::Stone,
+        PixelType::Grass,
+    ];
+
+    /// Every material, the built-in ones first and then the custom ones in the order they were added
+    pub fn all() -> Vec<PixelType> {
+        let custom = CUSTOM_MATERIALS.with_borrow(|materials| materials.len());
+        Self::BUILT_IN
+            .into_iter()
+            .chain((0..custom).map(|id| PixelType::Custom(id as u8)))
+            .collect()
+    }
+
+    /// The material at `index` in `all()`, if there is one
+    pub fn from_index(index: usize) -> Option<PixelType> {
+        if let Some(pixel_type) = Self::BUILT_IN.get(index) {
+            return Some(*pixel_type);
         }
+        let id = index - Self::BUILT_IN.len();
+        let custom = CUSTOM_MATERIALS.with_borrow(|materials| materials.len());
+        (id < custom).then_some(PixelType::Custom(id as u8))
+    }
+
+    /// Looks a material up by name, ignoring case
+    pub fn from_name(name: &str) -> Option<PixelType> {
+        Self::all()
+            .into_iter()
+            .find(|pixel_type| pixel_type.get().eq_ignore_ascii_case(name))
+    }
+
+    /// Adds a material, or updates the color and category of the custom material with this name.
+    /// Names are single words, so they can be typed in the console
+    pub fn define(name: &str, color: Color, category: Category) -> Result<PixelType, String> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || character == '_')
+        {
+            return Err(format!(
+                "'{name}' can't be a material name, use letters, digits and _"
+            ));
+        }
+        if let Some(pixel_type) = Self::BUILT_IN
+            .into_iter()
+            .find(|pixel_type| pixel_type.get().eq_ignore_ascii_case(name))
+        {
+            return Err(format!("{} is a built-in material", pixel_type.get()));
+        }
+        CUSTOM_MATERIALS.with_borrow_mut(|materials| {
+            if let Some(id) = materials
+                .iter()
+                .position(|material| material.name.eq_ignore_ascii_case(name))
+            {
+                materials[id].color = color;
+                materials[id].category = category;
+                return Ok(PixelType::Custom(id as u8));
+            }
+            if materials.len() == MAX_CUSTOM {
+                return Err(format!(
+                    "can't add {name}, there are already {MAX_CUSTOM} custom materials"
+                ));
+            }
+            materials.push(CustomMaterial {
+                name: String::from(name).leak(),
+                color,
+                category,
+            });
+            Ok(PixelType::Custom((materials.len() - 1) as u8))
+        })
+    }
+
+    fn with_custom<T>(id: u8, read: impl FnOnce(&CustomMaterial) -> T) -> T {
+        CUSTOM_MATERIALS.with_borrow(|materials| read(&materials[id as usize]))
+    }
+
+    /// Where this material is in `all()`, which is less than `MATERIAL_COUNT`
+    pub fn index(self) -> usize {
+        match self {
+            PixelType::Custom(id) => Self::BUILT_IN.len() + id as usize,
+            pixel_type => Self::BUILT_IN
+                .iter()
+                .position(|built_in| *built_in == pixel_type)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Selects the next material in `all()`. Air is skipped, it is selected on purpose or not at all
+    pub fn next(&mut self) {
+        self.step(1);
     }
     pub fn previous(&mut self) {
-        match *self {
-            PixelType::Sand => *self = PixelType::Grass,
-            PixelType::Grass => *self = PixelType::Stone,
-            PixelType::Stone => *self = PixelType::Dirt,
-            PixelType::Dirt => *self = PixelType::Water,
-            PixelType::Water => *self = PixelType::Sand,
-            _ => (),
+        self.step(-1);
+    }
+    fn step(&mut self, direction: isize) {
+        if *self == PixelType::Air {
+            return;
+        }
+        let materials: Vec<PixelType> = Self::all()
+            .into_iter()
+            .filter(|pixel_type| *pixel_type != PixelType::Air)
+            .collect();
+        if let Some(index) = materials.iter().position(|pixel_type| pixel_type == self) {
+            let next = (index as isize + direction).rem_euclid(materials.len() as isize);
+            *self = materials[next as usize];
         }
     }
 
-    pub fn get(&self) -> &str {
+    pub fn get(&self) -> &'static str {
         match self {
             PixelType::Sand => "Sand",
             PixelType::Water => "Water",
@@ -40,10 +195,22 @@ impl PixelType {
             PixelType::Stone => "Stone",
             PixelType::Grass => "Grass",
             PixelType::Air => "Air",
+            PixelType::Custom(id) => Self::with_custom(*id, |material| material.name),
         }
     }
 
-    pub fn to_color(&self) -> Color {
+    pub fn category(&self) -> Category {
+        match self {
+            PixelType::Sand => Category::Powder,
+            PixelType::Water => Category::Liquid,
+            PixelType::Dirt | PixelType::Stone | PixelType::Grass => Category::Solid,
+            // Air is empty space, painting it erases
+            PixelType::Air => Category::Special,
+            PixelType::Custom(id) => Self::with_custom(*id, |material| material.category),
+        }
+    }
+
+    pub fn to_color(self) -> Color {
         match self {
             PixelType::Sand => BEIGE,
             PixelType::Water => BLUE,
@@ -56,16 +223,27 @@ impl PixelType {
                 b: 0.0,
                 a: 0.0,
             },
+            PixelType::Custom(id) => Self::with_custom(id, |material| material.color),
         }
     }
 
+    /// Where the pixel at `x`, `y` wants to go this tick. A plugin or script for this material
+    /// replaces the built-in behaviour, plugins go first
     pub fn update(
         &self,
         chunk: &Chunk,
         x: i32,
         y: i32,
         rng: &RandGenerator,
+        scripts: &MaterialScripts,
+        plugins: &PluginHost,
     ) -> Option<GridMovement> {
+        if let Some(movement) = plugins.update(*self, chunk, x, y, rng) {
+            return movement;
+        }
+        if let Some(movement) = scripts.update(*self, chunk, x, y, rng) {
+            return movement;
+        }
         match self {
             PixelType::Sand => update_sand(chunk, x, y, rng),
             PixelType::Water => update_water(chunk, x, y, rng),
@@ -150,7 +328,7 @@ impl PixelType {
                 return true;
             }
         }
-        return false;
+        false
     }
 
     pub fn settle(
@@ -213,33 +391,55 @@ impl PixelType {
                 return true;
             }
         }
-        return false;
+        false
     }
 }
 
-pub fn draw_pixel(pixel_type: PixelType, x: i32, y: i32) {
-    let x = x as f32;
-    let y = y as f32;
-    let w = 1.0;
-    let h = 1.0;
-    match pixel_type {
-        PixelType::Sand => draw_rectangle(x, y, w, h, BEIGE),
-        PixelType::Water => draw_rectangle(x, y, w, h, BLUE),
-        PixelType::Dirt => draw_rectangle(x, y, w, h, DARKBROWN),
-        PixelType::Stone => draw_rectangle(x, y, w, h, GRAY),
-        PixelType::Grass => draw_rectangle(x, y, w, h, DARKGREEN),
-        PixelType::Air => draw_rectangle(
-            x,
-            y,
-            w,
-            h,
-            Color {
-                r: 0.0,
-                g: 0.0,
-                b: 0.0,
-                a: 0.0,
+/// How a material is written to files. Built-in materials keep their plain names. Custom ones are
+/// stored by name, since their ids depend on the order the scripts were loaded in
+#[derive(Serialize, Deserialize)]
+enum MaterialName {
+    Sand,
+    Water,
+    Air,
+    Dirt,
+    Stone,
+    Grass,
+    Custom(String),
+}
+
+impl From<PixelType> for MaterialName {
+    fn from(pixel_type: PixelType) -> Self {
+        match pixel_type {
+            PixelType::Sand => MaterialName::Sand,
+            PixelType::Water => MaterialName::Water,
+            PixelType::Air => MaterialName::Air,
+            PixelType::Dirt => MaterialName::Dirt,
+            PixelType::Stone => MaterialName::Stone,
+            PixelType::Grass => MaterialName::Grass,
+            PixelType::Custom(_) => MaterialName::Custom(pixel_type.get().to_string()),
+        }
+    }
+}
+
+impl TryFrom<MaterialName> for PixelType {
+    type Error = String;
+
+    fn try_from(name: MaterialName) -> Result<Self, Self::Error> {
+        Ok(match name {
+            MaterialName::Sand => PixelType::Sand,
+            MaterialName::Water => PixelType::Water,
+            MaterialName::Air => PixelType::Air,
+            MaterialName::Dirt => PixelType::Dirt,
+            MaterialName::Stone => PixelType::Stone,
+            MaterialName::Grass => PixelType::Grass,
+            // A file can mention a material whose script isn't loaded (yet). It is added without
+            // a behaviour, so the cells survive until the script shows up and gives it its color
+            MaterialName::Custom(name) => match PixelType::from_name(&name) {
+                Some(pixel_type) => pixel_type,
+                None => PixelType::define(&name, MAGENTA, Category::Special)?,
             },
-        ),
+        })
     }
 }
 
@@ -263,7 +463,7 @@ pub fn update_sand(
     if pixel_type.fall(pixel_grid, &mut grid_movement, direction) {
         return Some(grid_movement);
     }
-    return None;
+    None
 }
 
 pub fn update_water(
@@ -291,5 +491,101 @@ pub fn update_water(
         return Some(grid_movement);
     }
 
-    return None;
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_materials_come_first() {
+        for (index, pixel_type) in PixelType::BUILT_IN.into_iter().enumerate() {
+            assert_eq!(pixel_type.index(), index);
+        }
+        let slime = PixelType::define("slime", GREEN, Category::Liquid).unwrap();
+        assert_eq!(slime.index(), PixelType::BUILT_IN.len());
+        assert_eq!(PixelType::all().last(), Some(&slime));
+    }
+
+    #[test]
+    fn looks_materials_up_by_index() {
+        assert_eq!(PixelType::from_index(2), Some(PixelType::Air));
+        assert_eq!(PixelType::from_index(PixelType::BUILT_IN.len()), None);
+        let tar = PixelType::define("tar", BLACK, Category::Liquid).unwrap();
+        assert_eq!(PixelType::from_index(tar.index()), Some(tar));
+        assert_eq!(PixelType::from_index(tar.index() + 1), None);
+    }
+
+    #[test]
+    fn defining_a_name_again_keeps_its_id() {
+        let ice = PixelType::define("Ice", WHITE, Category::Solid).unwrap();
+        let again = PixelType::define("ICE", SKYBLUE, Category::Powder).unwrap();
+        assert_eq!(ice, again);
+        assert_eq!(ice.get(), "Ice");
+        assert_eq!(ice.to_color(), SKYBLUE);
+        assert_eq!(ice.category(), Category::Powder);
+        assert_eq!(PixelType::from_name("ice"), Some(ice));
+    }
+
+    #[test]
+    fn rejects_built_in_and_unusable_names() {
+        assert!(PixelType::define("water", BLUE, Category::Liquid).is_err());
+        for name in ["", "hot lava", "lava!", "\u{e9}"] {
+            assert!(
+                PixelType::define(name, RED, Category::Liquid).is_err(),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn stops_at_the_custom_material_limit() {
+        for id in 0..MAX_CUSTOM {
+            PixelType::define(&format!("m{id}"), RED, Category::Solid).unwrap();
+        }
+        assert!(PixelType::define("one_more", RED, Category::Solid).is_err());
+        assert_eq!(PixelType::all().len(), MATERIAL_COUNT);
+        assert!(
+            PixelType::all()
+                .iter()
+                .all(|pixel_type| pixel_type.index() < MATERIAL_COUNT)
+        );
+    }
+
+    #[test]
+    fn files_store_custom_materials_by_name() {
+        let acid = PixelType::define("acid", YELLOW, Category::Liquid).unwrap();
+        let text = ron::to_string(&vec![PixelType::Sand, acid]).unwrap();
+        assert_eq!(text, "[Sand,Custom(\"acid\")]");
+        let read: Vec<PixelType> = ron::from_str(&text).unwrap();
+        assert_eq!(read, [PixelType::Sand, acid]);
+    }
+
+    #[test]
+    fn unknown_materials_in_files_are_added_without_a_look() {
+        let read: PixelType = ron::from_str("Custom(\"oil\")").unwrap();
+        assert_eq!(read.get(), "oil");
+        assert_eq!(read.to_color(), MAGENTA);
+        assert!(ron::from_str::<PixelType>("Custom(\"no spaces\")").is_err());
+    }
+
+    #[test]
+    fn cycling_skips_air_and_includes_custom_materials() {
+        let mut pixel_type = PixelType::Grass;
+        pixel_type.next();
+        assert_eq!(pixel_type, PixelType::Sand);
+        pixel_type.previous();
+        assert_eq!(pixel_type, PixelType::Grass);
+
+        let gold = PixelType::define("gold", GOLD, Category::Powder).unwrap();
+        pixel_type.next();
+        assert_eq!(pixel_type, gold);
+        pixel_type.next();
+        assert_eq!(pixel_type, PixelType::Sand);
+
+        let mut air = PixelType::Air;
+        air.next();
+        assert_eq!(air, PixelType::Air);
+    }
 }
@@ -1,12 +1,22 @@
 use crate::{
-    CHUNK_SIZE,
-    pixel::{PixelType, draw_pixel},
+    chunk_size,
+    history::Snapshot,
+    pixel::{MATERIAL_COUNT, PixelType},
+    plugin::PluginHost,
+    rigid_body::{DETECT_EVERY, RigidBody, find_bodies},
+    rules::RuleSet,
+    scripting::MaterialScripts,
 };
 use macroquad::{
     prelude::*,
     rand::{ChooseRandom, RandGenerator},
 };
-use std::collections::HashMap;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug)]
 pub struct ChunkPosition {
@@ -15,21 +25,14 @@ pub struct ChunkPosition {
 }
 
 impl ChunkPosition {
-    pub fn new(chunk_key: (i32, i32), chunk_coordinate: (i32, i32)) -> Self {
-        Self {
-            chunk_key,
-            chunk_coordinate,
-        }
-    }
-
     pub fn from_world_position(world_position: Vec2) -> ChunkPosition {
         let (wx, wy) = (world_position.x as i32, world_position.y as i32);
 
-        let cx = wx.div_euclid(CHUNK_SIZE.0 as i32);
-        let cy = wy.div_euclid(CHUNK_SIZE.1 as i32);
+        let cx = wx.div_euclid(chunk_size().0 as i32);
+        let cy = wy.div_euclid(chunk_size().1 as i32);
 
-        let lx = wx.rem_euclid(CHUNK_SIZE.0 as i32);
-        let ly = wy.rem_euclid(CHUNK_SIZE.1 as i32);
+        let lx = wx.rem_euclid(chunk_size().0 as i32);
+        let ly = wy.rem_euclid(chunk_size().1 as i32);
 
         Self {
             chunk_key: (cx, cy),
@@ -37,33 +40,110 @@ impl ChunkPosition {
         }
     }
 }
+/// How many cells scripts and plugins can look and move away from the cell they update
+pub const NEIGHBOURHOOD_REACH: i32 = 2;
+
+/// A movement that crossed a chunk border, from and to in world pixels
+pub type CrossChunkMove = ((i32, i32), (i32, i32));
+
 pub struct ChunkGrid {
-    grid: HashMap<(i32, i32), Chunk>,
-    _seed: u64,
+    // A BTreeMap so chunks are always updated in the same order, which keeps the simulation deterministic
+    grid: BTreeMap<(i32, i32), Chunk>,
+    seed: u64,
     rng: RandGenerator,
+    tick: u64,
+    // The movements that crossed a chunk border last tick
+    cross_chunk_moves: Vec<CrossChunkMove>,
+    // How long applying those movements took
+    cross_chunk_cost: Duration,
+    scripts: MaterialScripts,
+    plugins: PluginHost,
+    rules: RuleSet,
+    bodies: Vec<RigidBody>,
+    // How long moving the bodies and looking for new ones took last tick
+    bodies_cost: Duration,
 }
 
 impl ChunkGrid {
-    pub fn new(_seed: u64, rng: RandGenerator) -> Self {
-        let mut grid = HashMap::new();
-        grid.insert((0, 0), Chunk::new(CHUNK_SIZE, _seed, (0, 0)));
-        grid.insert((0, 1), Chunk::new(CHUNK_SIZE, _seed, (0, 1)));
-        grid.insert((1, 0), Chunk::new(CHUNK_SIZE, _seed, (1, 0)));
-        grid.insert((1, 1), Chunk::new(CHUNK_SIZE, _seed, (1, 1)));
-        Self { grid, _seed, rng }
+    pub fn new(seed: u64, rng: RandGenerator) -> Self {
+        let mut grid = BTreeMap::new();
+        grid.insert((0, 0), Chunk::new(chunk_size(), seed, (0, 0)));
+        grid.insert((0, 1), Chunk::new(chunk_size(), seed, (0, 1)));
+        grid.insert((1, 0), Chunk::new(chunk_size(), seed, (1, 0)));
+        grid.insert((1, 1), Chunk::new(chunk_size(), seed, (1, 1)));
+        Self {
+            grid,
+            seed,
+            rng,
+            tick: 0,
+            cross_chunk_moves: vec![],
+            cross_chunk_cost: Duration::ZERO,
+            scripts: MaterialScripts::default(),
+            plugins: PluginHost::default(),
+            rules: RuleSet::default(),
+            bodies: vec![],
+            bodies_cost: Duration::ZERO,
+        }
+    }
+
+    /// Replaces the material scripts, they are used from the next tick on
+    pub fn set_scripts(&mut self, scripts: MaterialScripts) {
+        self.scripts = scripts;
+    }
+
+    pub fn scripts(&self) -> &MaterialScripts {
+        &self.scripts
+    }
+
+    /// Replaces the plugins, they are used from the next tick on
+    pub fn set_plugins(&mut self, plugins: PluginHost) {
+        self.plugins = plugins;
+    }
+
+    pub fn plugins(&self) -> &PluginHost {
+        &self.plugins
+    }
+
+    /// Replaces the automaton rules, they are used from the next tick on
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
     }
 
     pub fn update(&mut self) {
+        // Reseed the RNG from the seed and the current tick, so every tick plays out the same
+        // no matter where we started from. This is what makes rewinding to a snapshot and
+        // watching it again possible
+        self.rng
+            .srand(self.seed ^ self.tick.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        self.tick += 1;
+        self.plugins.start_tick();
+        for chunk in self.grid.values_mut() {
+            chunk.last_updates.clear();
+        }
+        // The automaton rules go first, on the grid as it was at the start of the tick.
+        // They run on the whole grid at once so their patterns see across chunk borders
+        if !self.rules.is_empty() {
+            self.apply_rules();
+        }
+
         // Updating should be multiple stages:
         // First: apply all in-chunk movements
         // Second: get all cross-chunk movements for each chunk
         // Third: apply all cross-chunk movements
         let mut cross_chunk_movements: Vec<Vec<GridMovement>> = vec![];
-        for ((x, y), chunk) in self.grid.iter_mut() {
-            cross_chunk_movements.push(chunk.update(&self.rng)); // Update all in-chunk movements and return all crosschunk movements
+        for chunk in self.grid.values_mut() {
+            let start = Instant::now();
+            cross_chunk_movements.push(chunk.update(&self.rng, &self.scripts, &self.plugins)); // Update all in-chunk movements and return all crosschunk movements
+            chunk.update_cost = start.elapsed();
         }
 
         // Apply all cross chunk movements
+        let start = Instant::now();
+        self.cross_chunk_moves.clear();
         for chunk in cross_chunk_movements {
             for movement in chunk {
                 match movement.new_chunk {
@@ -77,12 +157,12 @@ impl ChunkGrid {
                             let old_chunk = self
                                 .grid
                                 .get_mut(&movement.old_chunk.unwrap())
-                                .expect(format!("Expected a chunk at {:?}", chunk_key).as_str());
+                                .unwrap_or_else(|| panic!("Expected a chunk at {:?}", chunk_key));
                             old_chunk.remove(movement.old_position.0, movement.old_position.1);
                             let chunk = self
                                 .grid
                                 .get_mut(&chunk_key)
-                                .expect(format!("Expected a chunk at {:?}", chunk_key).as_str());
+                                .unwrap_or_else(|| panic!("Expected a chunk at {:?}", chunk_key));
                             if chunk
                                 .query(movement.new_position.0, movement.new_position.1)
                                 .is_free()
@@ -92,7 +172,10 @@ impl ChunkGrid {
                                     movement.new_position.1,
                                     movement.pixel_type,
                                 );
-                            } else {
+                                self.cross_chunk_moves.push((
+                                    movement.old_world_position(),
+                                    movement.new_world_position(),
+                                ));
                             }
                         }
                     }
@@ -100,10 +183,36 @@ impl ChunkGrid {
             }
         }
 
+        self.cross_chunk_cost = start.elapsed();
+
+        let start = Instant::now();
+        self.update_bodies();
+        self.bodies_cost = start.elapsed();
+
         // Update texture
         self.update_texture();
     }
 
+    /// Moves the rigid bodies, drops the ones that came to rest, and every few ticks
+    /// turns regions that lost their support into new ones
+    fn update_bodies(&mut self) {
+        let mut bodies = std::mem::take(&mut self.bodies);
+        bodies.retain_mut(|body| body.update(self));
+        if self.tick.is_multiple_of(DETECT_EVERY) {
+            let found = find_bodies(self, &bodies);
+            bodies.extend(found);
+        }
+        self.bodies = bodies;
+    }
+
+    pub fn bodies(&self) -> &[RigidBody] {
+        &self.bodies
+    }
+
+    pub fn bodies_cost(&self) -> Duration {
+        self.bodies_cost
+    }
+
     pub fn update_texture(&mut self) {
         for ((_, _), chunk) in self.grid.iter_mut() {
             chunk.update_texture();
@@ -114,85 +223,226 @@ impl ChunkGrid {
         for ((_x, _y), chunk) in self.grid.iter_mut() {
             chunk.clear();
         }
+        self.bodies.clear();
+    }
+
+    /// Empties one chunk, returns false if there is no chunk at `key`
+    pub fn clear_chunk(&mut self, key: (i32, i32)) -> bool {
+        match self.grid.get_mut(&key) {
+            Some(chunk) => {
+                chunk.clear();
+                chunk.update_texture();
+                true
+            }
+            None => false,
+        }
     }
 
+    /// How many pixels in the world are not Air
     pub fn get_total_pixels(&self) -> usize {
-        let mut res = 0;
-        for ((_, _), chunk) in self.grid.iter() {
-            res += chunk.chunk.len();
+        self.grid.values().map(Chunk::pixel_count).sum()
+    }
+
+    /// How many pixels of every material the world has, indexed by PixelType::index()
+    pub fn material_counts(&self) -> [usize; MATERIAL_COUNT] {
+        let mut counts = [0; MATERIAL_COUNT];
+        for chunk in self.grid.values() {
+            for (count, chunk_count) in counts.iter_mut().zip(chunk.counts()) {
+                *count += chunk_count;
+            }
         }
-        res
+        counts
     }
 
-    pub fn draw(&self) {
-        for ((chunk_key_x, chunk_key_y), chunk) in self.grid.iter() {
-            chunk.draw(*chunk_key_x, *chunk_key_y);
+    /// How many pixels moved last tick, inside their chunk or across a border
+    pub fn moving_pixels(&self) -> usize {
+        self.grid.values().map(Chunk::moved_count).sum::<usize>() + self.cross_chunk_moves.len()
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Clears the grid and starts counting ticks from zero again with the given seed.
+    /// Together with the same inputs this plays out exactly the same every time
+    pub fn restart(&mut self, seed: u64) {
+        self.clear();
+        self.seed = seed;
+        self.tick = 0;
+        self.update_texture();
+    }
+
+    /// Takes a snapshot of the whole grid. The chunk data is shared with the grid
+    /// and only copied once the grid writes to a chunk again (copy-on-write)
+    pub fn snapshot(&self) -> Snapshot {
+        let chunks = self
+            .grid
+            .iter()
+            .map(|(key, chunk)| (*key, Rc::clone(&chunk.chunk)))
+            .collect();
+        Snapshot::new(self.tick, chunks, self.bodies.clone())
+    }
+
+    /// Replaces the contents of every chunk with the data from the snapshot
+    /// and rewinds the tick counter to the tick the snapshot was taken at
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        for (key, data) in snapshot.chunks() {
+            if let Some(chunk) = self.grid.get_mut(key) {
+                chunk.replace_data(Rc::clone(data));
+            }
         }
+        self.tick = snapshot.tick();
+        self.bodies = snapshot.bodies().to_vec();
+        self.update_texture();
     }
 
-    pub fn grid(&mut self) -> &mut HashMap<(i32, i32), Chunk> {
-        &mut self.grid
+    /// Draws every chunk, and returns how long rebuilding their textures took
+    pub fn draw(&self) -> Duration {
+        let mut texture_cost = Duration::ZERO;
+        for ((chunk_key_x, chunk_key_y), chunk) in self.grid.iter() {
+            texture_cost += chunk.draw(*chunk_key_x, *chunk_key_y);
+        }
+        texture_cost
     }
 
+    /// Sets the pixel at a world position. Positions outside of the loaded chunks are ignored
     pub fn set_pixel(&mut self, world_position: Vec2, pixel_type: PixelType) {
         let chunk_position = ChunkPosition::from_world_position(world_position);
-        self.grid.get_mut(&chunk_position.chunk_key).unwrap().set(
-            chunk_position.chunk_coordinate.0,
-            chunk_position.chunk_coordinate.1,
-            pixel_type,
-        );
+        if let Some(chunk) = self.grid.get_mut(&chunk_position.chunk_key) {
+            chunk.set(
+                chunk_position.chunk_coordinate.0,
+                chunk_position.chunk_coordinate.1,
+                pixel_type,
+            );
+        }
     }
 
-    /// Check if the grid position (world position) is free, chunk-wide
-    /// This requires the supplied GridMovement struct to have a chunk key
-    /// and a chunk coordinate
-    /// It then checks the coordinate within that chunk if it is free
-    pub fn is_free(&self, grid_movement: &GridMovement) -> bool {
-        match grid_movement.new_chunk {
-            None => {
-                println!("chunk key not set! skipping movement");
-                false
+    /// The two corners of the smallest rectangle around every loaded chunk, both included, in world pixels
+    pub fn bounds(&self) -> Option<(Vec2, Vec2)> {
+        if self.grid.is_empty() {
+            return None;
+        }
+        let (min_x, min_y) = self.grid.keys().fold((i32::MAX, i32::MAX), |min, key| {
+            (min.0.min(key.0), min.1.min(key.1))
+        });
+        let (max_x, max_y) = self.grid.keys().fold((i32::MIN, i32::MIN), |max, key| {
+            (max.0.max(key.0), max.1.max(key.1))
+        });
+        let size = vec2(chunk_size().0 as f32, chunk_size().1 as f32);
+        Some((
+            vec2(min_x as f32, min_y as f32) * size,
+            vec2(max_x as f32 + 1.0, max_y as f32 + 1.0) * size - 1.0,
+        ))
+    }
+
+    pub fn chunk(&self, key: (i32, i32)) -> Option<&Chunk> {
+        self.grid.get(&key)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (&(i32, i32), &Chunk)> {
+        self.grid.iter()
+    }
+
+    /// The movements that crossed a chunk border last tick
+    pub fn cross_chunk_moves(&self) -> &[CrossChunkMove] {
+        &self.cross_chunk_moves
+    }
+
+    pub fn cross_chunk_cost(&self) -> Duration {
+        self.cross_chunk_cost
+    }
+
+    /// Finds what every rule does on the grid and applies it in random order. A rewrite is skipped
+    /// if one before it already changed a cell it touches. The changed cells go in the
+    /// `last_updates` of their chunks
+    fn apply_rules(&mut self) {
+        let mut rewrites = vec![];
+        for (key, chunk) in &self.grid {
+            for y in 0..chunk.height {
+                for x in 0..chunk.width {
+                    if let Some(rewrite) = self.rules.rewrite(self, *key, (x, y), &self.rng) {
+                        rewrites.push(rewrite);
+                    }
+                }
             }
-            Some(chunk_key) => {
-                let chunk = self
-                    .grid
-                    .get(&chunk_key)
-                    .expect(format!("Expected a chunk at {:?}", chunk_key).as_str());
-                if chunk
-                    .query(grid_movement.new_position.0, grid_movement.new_position.1)
-                    .is_free()
-                {
-                    true
-                } else {
-                    false
+        }
+        rewrites.shuffle_with_state(&self.rng);
+        for rewrite in rewrites {
+            if rewrite
+                .cells()
+                .any(|((x, y), seen, _)| self.get_pixel(vec2(x as f32, y as f32)) != Some(seen))
+            {
+                continue;
+            }
+            for ((x, y), seen, new) in rewrite.cells() {
+                if new == seen {
+                    continue;
+                }
+                let position = ChunkPosition::from_world_position(vec2(x as f32, y as f32));
+                if let Some(chunk) = self.grid.get_mut(&position.chunk_key) {
+                    let (x, y) = position.chunk_coordinate;
+                    chunk.set(x, y, new);
+                    chunk.last_updates.insert((x, y), new);
                 }
             }
         }
     }
 
-    /// Does the same check as is_free(). It checks the chunk coordinate
-    /// if it is free. But this returns the chunk if it is free.
-    /// Can be used to directly insert into the chunk that is checked.
-    pub fn get_chunk_if_free(&self, grid_movement: &GridMovement) -> Option<&Chunk> {
+    /// The 3x3 cells around `x`, `y` in the chunk at `key`, row by row. The cells past the chunk's
+    /// border are read from the chunks around it, and are None where no chunk is loaded
+    pub fn neighbourhood(&self, key: (i32, i32), (x, y): (i32, i32)) -> [Option<PixelType>; 9] {
+        let chunk = self.grid.get(&key);
+        std::array::from_fn(|index| {
+            let (cell_x, cell_y) = (x + index as i32 % 3 - 1, y + index as i32 / 3 - 1);
+            match chunk.map(|chunk| chunk.query(cell_x, cell_y)) {
+                Some(GridQuery::Hit(pixel_type)) => Some(pixel_type),
+                Some(GridQuery::None) => Some(PixelType::Air),
+                _ => {
+                    let (world_x, world_y) = to_world_position(key, (cell_x, cell_y));
+                    self.get_pixel(vec2(world_x as f32, world_y as f32))
+                }
+            }
+        })
+    }
+
+    /// Returns the pixel at a world position, or None if it is outside of the loaded chunks
+    pub fn get_pixel(&self, world_position: Vec2) -> Option<PixelType> {
+        let chunk_position = ChunkPosition::from_world_position(world_position);
+        let chunk = self.grid.get(&chunk_position.chunk_key)?;
+        chunk
+            .get(
+                chunk_position.chunk_coordinate.0,
+                chunk_position.chunk_coordinate.1,
+            )
+            .copied()
+    }
+
+    /// A random number in `low..high` from the grid's RNG, so anything random stays the same in a replay
+    pub fn random_range(&self, low: i32, high: i32) -> i32 {
+        self.rng.gen_range(low, high)
+    }
+
+    /// Check if the grid position (world position) is free, chunk-wide
+    /// This requires the supplied GridMovement struct to have a chunk key
+    /// and a chunk coordinate
+    /// It then checks the coordinate within that chunk if it is free
+    /// Positions in chunks that don't exist are never free, they act as the edge of the world
+    pub fn is_free(&self, grid_movement: &GridMovement) -> bool {
         match grid_movement.new_chunk {
             None => {
                 println!("chunk key not set! skipping movement");
-                None
+                false
             }
-            Some(chunk_key) => {
-                let chunk = self
-                    .grid
-                    .get(&chunk_key)
-                    .expect(format!("Expected a chunk at {:?}", chunk_key).as_str());
-                if chunk
+            Some(chunk_key) => match self.grid.get(&chunk_key) {
+                None => false,
+                Some(chunk) => chunk
                     .query(grid_movement.new_position.0, grid_movement.new_position.1)
-                    .is_free()
-                {
-                    Some(&chunk)
-                } else {
-                    None
-                }
-            }
+                    .is_free(),
+            },
         }
     }
 }
@@ -201,57 +451,59 @@ pub struct Chunk {
     width: i32,
     height: i32,
     key: (i32, i32),
-    chunk: Vec<PixelType>,
+    chunk: Rc<Vec<PixelType>>,
+    // How many pixels of every material this chunk has, indexed by PixelType::index().
+    // Kept up to date on every change, so nobody has to count them
+    counts: [usize; MATERIAL_COUNT],
     last_updates: HashMap<(i32, i32), PixelType>,
+    // Whether any pixel in this chunk tried to move or was rewritten last tick.
+    // Only shown in the inspector, every chunk is still updated every tick
+    active: bool,
+    // How long the last update took
+    update_cost: Duration,
 
-    texture: Texture2D,
+    // The texture is only created and filled when the chunk is drawn, so the simulation
+    // can run without a window (headless)
+    texture: RefCell<Option<Texture2D>>,
+    texture_dirty: Cell<bool>,
 
     _seed: u64,
 }
 impl Chunk {
     pub fn new(size: (usize, usize), _seed: u64, key: (i32, i32)) -> Self {
-        let chunk = vec![PixelType::Air; CHUNK_SIZE.0 as usize * CHUNK_SIZE.1 as usize];
+        let chunk = Rc::new(vec![PixelType::Air; chunk_size().0 * chunk_size().1]);
         let last_updates = HashMap::new();
-
-        let image = Image::gen_image_color(
-            CHUNK_SIZE.0 as u16,
-            CHUNK_SIZE.1 as u16,
-            Color {
-                r: 0.0,
-                g: 0.0,
-                b: 0.0,
-                a: 0.0,
-            },
-        );
-
-        let texture = Texture2D::from_image(&image);
-        texture.set_filter(FilterMode::Nearest);
+        let mut counts = [0; MATERIAL_COUNT];
+        counts[PixelType::Air.index()] = chunk.len();
 
         Self {
             width: size.0 as i32,
             height: size.1 as i32,
             key,
             chunk,
+            counts,
             last_updates,
+            active: true,
+            update_cost: Duration::ZERO,
 
-            texture,
+            texture: RefCell::new(None),
+            texture_dirty: Cell::new(true),
 
             _seed,
         }
     }
-    pub fn chunk(&self) -> &Vec<PixelType> {
-        return &self.chunk;
-    }
-
-    pub fn chunk_mut(&mut self) -> &mut Vec<PixelType> {
-        return &mut self.chunk;
-    }
-
     /// The update function returns a vector of cross gridmovements. The return type is only used
     /// by the parent struct ChunkGrid to handle cross chunk movements.
     /// All mvoements in-chunk are handled by the chunk itself in their update function
-    pub fn update(&mut self, rng: &RandGenerator) -> Vec<GridMovement> {
-        self.last_updates.clear();
+    pub fn update(
+        &mut self,
+        rng: &RandGenerator,
+        scripts: &MaterialScripts,
+        plugins: &PluginHost,
+    ) -> Vec<GridMovement> {
+        // The cells the automaton rules changed this tick are already in last_updates,
+        // they are left alone by the material updates below
+        let rewritten = !self.last_updates.is_empty();
         // We filter_map() the hashmap
         // First we match the PixelType to call the appropriate pixel update function
         // Then in each update function we check certain bounds
@@ -260,18 +512,23 @@ impl Chunk {
         // to update the hashmap
         ////Returns://////(Old X, Y)  (New X, Y)  Pixel to move
         let mut changes: Vec<GridMovement> = vec![];
-        for y in 0..CHUNK_SIZE.1 {
-            for x in 0..CHUNK_SIZE.0 {
-                if let Some(pixel_type) = self.get(x as i32, y as i32) {
-                    if let Some(movement) = pixel_type.update(self, x as i32, y as i32, rng) {
-                        changes.push(movement);
-                    }
+        for y in 0..chunk_size().1 {
+            for x in 0..chunk_size().0 {
+                if rewritten && self.last_updates.contains_key(&(x as i32, y as i32)) {
+                    continue;
+                }
+                if let Some(pixel_type) = self.get(x as i32, y as i32)
+                    && let Some(movement) =
+                        pixel_type.update(self, x as i32, y as i32, rng, scripts, plugins)
+                {
+                    changes.push(movement);
                 }
             }
         }
+        self.active = rewritten || !changes.is_empty();
         // Before we apply the changes we shuffle the changes vector, so that the updates are applied in random order
         // We do this to make it seem more natural and to prevent certain softlocks
-        changes.shuffle();
+        changes.shuffle_with_state(rng);
         // Here we loop over the changes vector and apply all modifications in the grid hashmap
         // First we check if the new position is out of bounds and should move to a different chunk
         // We also check if the new position is already been occupied in a previous move byh another pixel
@@ -307,10 +564,16 @@ impl Chunk {
         cross_chunk_movements
     }
 
+    /// Marks the texture as out of date. It is filled again the next time the chunk is drawn
     pub fn update_texture(&mut self) {
+        self.texture_dirty.set(true);
+    }
+
+    /// The chunk as an image, one image pixel per pixel, in the colors it is drawn with
+    pub fn to_image(&self) -> Image {
         let mut image = Image::gen_image_color(
-            CHUNK_SIZE.0 as u16,
-            CHUNK_SIZE.1 as u16,
+            chunk_size().0 as u16,
+            chunk_size().1 as u16,
             Color {
                 r: 0.0,
                 g: 0.0,
@@ -319,24 +582,41 @@ impl Chunk {
             },
         );
 
-        for y in 0..CHUNK_SIZE.1 {
-            for x in 0..CHUNK_SIZE.0 {
+        for y in 0..chunk_size().1 {
+            for x in 0..chunk_size().0 {
                 if let Some(pixel_type) = self.get(x as i32, y as i32) {
                     let color = pixel_type.to_color();
                     image.set_pixel(x as u32, y as u32, color);
                 }
             }
         }
-
-        self.texture.update(&image);
+        image
     }
 
-    pub fn draw(&self, chunk_key_x: i32, chunk_key_y: i32) {
-        let chunk_x = chunk_key_x * CHUNK_SIZE.0 as i32;
-        let chunk_y = chunk_key_y * CHUNK_SIZE.1 as i32;
-
+    /// Draws the chunk, and returns how long rebuilding its texture took
+    pub fn draw(&self, chunk_key_x: i32, chunk_key_y: i32) -> Duration {
+        let chunk_x = chunk_key_x * chunk_size().0 as i32;
+        let chunk_y = chunk_key_y * chunk_size().1 as i32;
+
+        let start = Instant::now();
+        let mut texture = self.texture.borrow_mut();
+        if self.texture_dirty.replace(false) || texture.is_none() {
+            let image = self.to_image();
+            match texture.as_ref() {
+                Some(texture) => texture.update(&image),
+                None => {
+                    let new_texture = Texture2D::from_image(&image);
+                    new_texture.set_filter(FilterMode::Nearest);
+                    *texture = Some(new_texture);
+                }
+            }
+        }
+        let texture_cost = start.elapsed();
+        let Some(texture) = texture.as_ref() else {
+            return texture_cost;
+        };
         draw_texture_ex(
-            &self.texture,
+            texture,
             chunk_x as f32,
             chunk_y as f32,
             WHITE,
@@ -344,18 +624,7 @@ impl Chunk {
                 ..Default::default()
             },
         );
-
-        /*
-        // Here we loop over the pixel grid to draw all the pixels
-        for y in 0..CHUNK_SIZE.1 {
-            for x in 0..CHUNK_SIZE.0 {
-                let index = Chunk::index(x as i32, y as i32);
-                if let Some(pixel_type) = self.chunk.get(index) {
-                    draw_pixel(*pixel_type, chunk_x + x as i32, chunk_y + y as i32);
-                }
-            }
-        }
-        */
+        texture_cost
     }
 
     pub fn query(&self, x: i32, y: i32) -> GridQuery {
@@ -377,30 +646,81 @@ impl Chunk {
             GridQuery::None
         }
     }
+    pub fn key(&self) -> (i32, i32) {
+        self.key
+    }
+    /// The cells within `reach` of `x`, `y`, row by row. Cells outside the chunk are None
+    pub fn neighbourhood(
+        &self,
+        x: i32,
+        y: i32,
+        reach: i32,
+    ) -> impl Iterator<Item = Option<PixelType>> + '_ {
+        (-reach..=reach).flat_map(move |dy| {
+            (-reach..=reach).map(move |dx| match self.query(x + dx, y + dy) {
+                GridQuery::OutOfBounds => None,
+                GridQuery::Hit(pixel_type) => Some(pixel_type),
+                GridQuery::None => Some(PixelType::Air),
+            })
+        })
+    }
     pub fn index(x: i32, y: i32) -> usize {
-        (y * CHUNK_SIZE.0 as i32 + x) as usize
+        (y * chunk_size().0 as i32 + x) as usize
     }
     pub fn get(&self, x: i32, y: i32) -> Option<&PixelType> {
         let index = Chunk::index(x, y);
         self.chunk.get(index)
     }
+    // Writing goes through Rc::make_mut, which copies the chunk data first
+    // if a snapshot is still holding on to it
     pub fn set(&mut self, x: i32, y: i32, pixel: PixelType) {
         let index = Chunk::index(x, y);
-        self.chunk[index] = pixel;
+        let old = std::mem::replace(&mut Rc::make_mut(&mut self.chunk)[index], pixel);
+        self.counts[old.index()] -= 1;
+        self.counts[pixel.index()] += 1;
     }
     pub fn remove(&mut self, x: i32, y: i32) -> PixelType {
-        let index = Chunk::index(x, y);
-        let old = self.chunk[index];
-        self.chunk[index] = PixelType::Air;
+        let old = self.chunk[Chunk::index(x, y)];
+        self.set(x, y, PixelType::Air);
         old
     }
     pub fn clear(&mut self) {
-        self.chunk.clear();
-        for _ in 0..CHUNK_SIZE.0 {
-            for _ in 0..CHUNK_SIZE.1 {
-                self.chunk.push(PixelType::Air);
-            }
+        // Swap in fresh data instead of clearing in place, so snapshots keep their copy
+        self.replace_data(Rc::new(vec![
+            PixelType::Air;
+            chunk_size().0 * chunk_size().1
+        ]));
+    }
+    /// Swaps in new chunk data, like from a snapshot, and counts its materials
+    pub fn replace_data(&mut self, data: Rc<Vec<PixelType>>) {
+        self.counts = [0; MATERIAL_COUNT];
+        for pixel_type in data.iter() {
+            self.counts[pixel_type.index()] += 1;
         }
+        self.chunk = data;
+    }
+    /// How many pixels of every material this chunk has, indexed by PixelType::index()
+    pub fn counts(&self) -> &[usize; MATERIAL_COUNT] {
+        &self.counts
+    }
+    /// How many pixels moved inside this chunk last tick
+    pub fn moved_count(&self) -> usize {
+        self.last_updates.len()
+    }
+    /// Whether anything in this chunk tried to move or was rewritten last tick
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+    pub fn update_cost(&self) -> Duration {
+        self.update_cost
+    }
+    /// The local positions pixels moved to inside this chunk last tick
+    pub fn moved_cells(&self) -> impl Iterator<Item = &(i32, i32)> {
+        self.last_updates.keys()
+    }
+    /// How many pixels in this chunk are not Air
+    pub fn pixel_count(&self) -> usize {
+        self.chunk.len() - self.counts[PixelType::Air.index()]
     }
     pub fn width(&self) -> i32 {
         self.width
@@ -444,14 +764,43 @@ impl GridMovement {
         }
     }
 
+    /// The cell at `position` moving by `offset` and turning into `new_type`, like a script or plugin
+    /// asked for. None if nothing would change, or if the cell it moves into isn't free
+    pub fn proposed(
+        chunk: &Chunk,
+        position: (i32, i32),
+        offset: (i32, i32),
+        old_type: PixelType,
+        new_type: PixelType,
+    ) -> Option<Self> {
+        let new_position = (position.0 + offset.0, position.1 + offset.1);
+        if offset == (0, 0) {
+            return (new_type != old_type).then(|| Self::new(position, position, new_type));
+        }
+        chunk
+            .query(new_position.0, new_position.1)
+            .is_free()
+            .then(|| Self::new(position, new_position, new_type))
+    }
+
     pub fn out_of_bounds(&self) -> bool {
-        if self.new_position.0 as usize >= CHUNK_SIZE.0 || self.new_position.0 < 0 {
+        if self.new_position.0 as usize >= chunk_size().0 || self.new_position.0 < 0 {
             return true;
         }
-        if self.new_position.1 as usize >= CHUNK_SIZE.1 || self.new_position.1 < 0 {
+        if self.new_position.1 as usize >= chunk_size().1 || self.new_position.1 < 0 {
             return true;
         }
-        return false;
+        false
+    }
+
+    /// Where the pixel moves from, in world pixels. Only valid once the chunk keys are set
+    pub fn old_world_position(&self) -> (i32, i32) {
+        to_world_position(self.old_chunk.unwrap_or_default(), self.old_position)
+    }
+
+    /// Where the pixel moves to, in world pixels. Only valid once the chunk keys are set
+    pub fn new_world_position(&self) -> (i32, i32) {
+        to_world_position(self.new_chunk.unwrap_or_default(), self.new_position)
     }
 
     pub fn set_chunk_keys(&mut self, current_chunk_key: (i32, i32)) {
@@ -466,26 +815,33 @@ impl GridMovement {
         let mut x = self.new_position.0;
         let mut y = self.new_position.1;
         // X Axis
-        if x >= CHUNK_SIZE.0 as i32 {
+        if x >= chunk_size().0 as i32 {
             new_chunk.0 += 1;
-            x -= CHUNK_SIZE.0 as i32;
+            x -= chunk_size().0 as i32;
         }
         if x < 0 {
             new_chunk.0 -= 1;
-            x += CHUNK_SIZE.0 as i32;
+            x += chunk_size().0 as i32;
         }
 
         // Y axis
-        if y >= CHUNK_SIZE.1 as i32 {
+        if y >= chunk_size().1 as i32 {
             new_chunk.1 += 1;
-            y -= CHUNK_SIZE.1 as i32;
-            println!("TEST");
+            y -= chunk_size().1 as i32;
         }
         if y < 0 {
             new_chunk.1 -= 1;
-            y += CHUNK_SIZE.1 as i32;
+            y += chunk_size().1 as i32;
         }
         self.new_position = (x, y);
         self.new_chunk = Some(new_chunk);
     }
 }
+
+/// Turns a chunk key and a position inside that chunk into a world position
+pub fn to_world_position(chunk_key: (i32, i32), position: (i32, i32)) -> (i32, i32) {
+    (
+        chunk_key.0 * chunk_size().0 as i32 + position.0,
+        chunk_key.1 * chunk_size().1 as i32 + position.1,
+    )
+}
@@ -0,0 +1,425 @@
+//! Compiled material and brush plugins, loaded from WebAssembly modules.
+//!
+//! Every `.wasm` file in the plugins folder is a plugin. It runs in its own sandbox with a fuel budget
+//! for every tick, and only ever sees the cells around the one it updates. The ABI:
+//!
+//! Imports, from the `sandbox` module:
+//! - `register_material(name_ptr: i32, name_len: i32) -> i32` takes over the updates of the material
+//!   with that name, like "Sand". Returns 0, or -1 when there is no such material
+//! - `define_material(name_ptr: i32, name_len: i32, color: i32, category: i32) -> i32` adds a material
+//!   to the palette and lets the plugin update it. `color` is `0xRRGGBB`, `category` is 0 for powders,
+//!   1 liquids, 2 gases, 3 solids or 4 special. Returns the index of the material, or -1 if it can't be added
+//! - `register_brush(name_ptr: i32, name_len: i32) -> i32` adds a brush tool and returns its id
+//!
+//! Exports:
+//! - `memory`
+//! - `init()`, optional, called once after loading to define and register materials and brushes
+//! - `neighbourhood() -> i32`, the address the 5x5 cells around the updated cell are written to, row by row.
+//!   One byte per cell: the material index, or 255 outside the chunk
+//! - `update(material: i32, x: i32, y: i32, random: i32) -> i32`, for registered materials, with the world
+//!   position and a number from the tick RNG. Negative to stay put, or `(dx + 2) | (dy + 2) << 4 | material << 8`
+//!   to move and become `material`
+//! - `brush(id: i32, size: i32, random: i32) -> i32`, for brush tools. Returns how many cells it wrote
+//! - `brush_cells() -> i32`, the address of those cells, three bytes each: `dx: i8, dy: i8, material: u8`
+use macroquad::{color::Color, rand::RandGenerator};
+use std::{
+    cell::{Cell, RefCell},
+    error::Error,
+    fs,
+    path::Path,
+};
+use wasmi::{
+    Caller, Config, Engine, Extern, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc, core::TrapCode,
+};
+
+use crate::{
+    pixel::{Category, PixelType},
+    pixel_grid::{Chunk, GridMovement, NEIGHBOURHOOD_REACH, to_world_position},
+};
+
+// How much work a plugin may do in one tick, so a slow or stuck plugin can't hang the simulation
+const FUEL_PER_TICK: u64 = 20_000_000;
+// How much work one use of a brush tool may do
+const FUEL_PER_BRUSH: u64 = 1_000_000;
+const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+const MAX_BRUSH_CELLS: usize = 16 * 1024;
+// The neighbourhood byte for cells outside the chunk
+const OUTSIDE: u8 = u8::MAX;
+
+// update(material, x, y, random) and brush(id, size, random)
+type UpdateFunc = TypedFunc<(i32, i32, i32, i32), i32>;
+type BrushFunc = TypedFunc<(i32, i32, i32), i32>;
+
+/// A cell a brush tool paints, relative to the mouse
+pub type BrushCell = ((i32, i32), PixelType);
+
+/// What a plugin registered while loading, kept in its store so the host functions can reach it
+struct PluginState {
+    limits: StoreLimits,
+    materials: Vec<PixelType>,
+    brushes: Vec<String>,
+}
+
+/// A loaded plugin. Its store is in a RefCell, because materials are updated through a shared grid
+struct Plugin {
+    name: String,
+    store: RefCell<Store<PluginState>>,
+    memory: Memory,
+    materials: Vec<PixelType>,
+    brushes: Vec<String>,
+    // The update function and where the neighbourhood goes, if the plugin has materials
+    update: Option<(UpdateFunc, usize)>,
+    // The brush function and where its cells come from, if the plugin has brushes
+    brush: Option<(BrushFunc, usize)>,
+    // Set after a trap, the plugin stays off until the plugins are loaded again
+    failed: Cell<bool>,
+    // Set when this tick's fuel ran out, its cells wait for the next tick
+    out_of_fuel: Cell<bool>,
+    // So running out of fuel is only reported once
+    fuel_reported: Cell<bool>,
+    // Set when a brush ran out of its own fuel. That only loses the stroke,
+    // the materials keep updating with the tick's fuel
+    brush_out_of_fuel: Cell<bool>,
+}
+
+impl Plugin {
+    /// Reports a trap, and switches the plugin off for this tick or for good
+    fn trapped(&self, error: &wasmi::Error) {
+        if error.as_trap_code() == Some(TrapCode::OutOfFuel) {
+            self.out_of_fuel.set(true);
+            if !self.fuel_reported.replace(true) {
+                println!(
+                    "Plugin {} ran out of fuel, its cells wait for the next tick",
+                    self.name
+                );
+            }
+        } else {
+            println!("Plugin {} stopped working: {error}", self.name);
+            self.failed.set(true);
+        }
+    }
+}
+
+/// The plugins from the plugins folder, and the engine that runs them
+pub struct PluginHost {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+}
+
+impl Default for PluginHost {
+    fn default() -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Self {
+            engine: Engine::new(&config),
+            plugins: vec![],
+        }
+    }
+}
+
+impl PluginHost {
+    /// Loads every plugin in `folder`. Plugins that fail to load are reported and skipped
+    pub fn load(folder: &Path) -> Self {
+        let mut host = Self::default();
+        let Ok(entries) = fs::read_dir(folder) else {
+            // No plugins folder means no plugins
+            return host;
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "wasm")
+            })
+            .collect();
+        paths.sort();
+        for path in paths {
+            match host.instantiate(&path) {
+                Ok(plugin) => {
+                    println!(
+                        "Loaded plugin {} with {} materials and {} brushes",
+                        plugin.name,
+                        plugin.materials.len(),
+                        plugin.brushes.len()
+                    );
+                    host.plugins.push(plugin);
+                }
+                Err(error) => println!("Failed to load plugin {}: {error}", path.display()),
+            }
+        }
+        host
+    }
+
+    fn instantiate(&self, path: &Path) -> Result<Plugin, Box<dyn Error>> {
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let module = Module::new(&self.engine, &fs::read(path)?[..])?;
+        let mut store = Store::new(
+            &self.engine,
+            PluginState {
+                limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(),
+                materials: vec![],
+                brushes: vec![],
+            },
+        );
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL_PER_TICK).map_err(wasmi::Error::from)?;
+
+        let mut linker = Linker::new(&self.engine);
+        linker.func_wrap(
+            "sandbox",
+            "register_material",
+            |mut caller: Caller<'_, PluginState>, pointer: i32, length: i32| -> i32 {
+                let Some(pixel_type) = read_string(&caller, pointer, length)
+                    .and_then(|name| PixelType::from_name(&name))
+                else {
+                    return -1;
+                };
+                caller.data_mut().materials.push(pixel_type);
+                0
+            },
+        )?;
+        linker.func_wrap(
+            "sandbox",
+            "define_material",
+            |mut caller: Caller<'_, PluginState>,
+             pointer: i32,
+             length: i32,
+             color: i32,
+             category: i32|
+             -> i32 {
+                let Some(name) = read_string(&caller, pointer, length) else {
+                    return -1;
+                };
+                let Some(category) = Category::ALL.get(category as u32 as usize) else {
+                    println!("Plugin material {name} has an unknown category {category}");
+                    return -1;
+                };
+                let [_, r, g, b] = color.to_be_bytes();
+                match PixelType::define(&name, Color::from_rgba(r, g, b, 255), *category) {
+                    Ok(pixel_type) => {
+                        caller.data_mut().materials.push(pixel_type);
+                        pixel_type.index() as i32
+                    }
+                    Err(error) => {
+                        println!("Plugin material {name}: {error}");
+                        -1
+                    }
+                }
+            },
+        )?;
+        linker.func_wrap(
+            "sandbox",
+            "register_brush",
+            |mut caller: Caller<'_, PluginState>, pointer: i32, length: i32| -> i32 {
+                let Some(name) = read_string(&caller, pointer, length) else {
+                    return -1;
+                };
+                let brushes = &mut caller.data_mut().brushes;
+                brushes.push(name);
+                brushes.len() as i32 - 1
+            },
+        )?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("the plugin exports no memory")?;
+        if let Ok(init) = instance.get_typed_func::<(), ()>(&store, "init") {
+            init.call(&mut store, ())?;
+        }
+
+        let materials = store.data().materials.clone();
+        let brushes = store.data().brushes.clone();
+        let update = match materials.is_empty() {
+            true => None,
+            false => Some((
+                instance.get_typed_func(&store, "update")?,
+                address(&instance, &mut store, "neighbourhood")?,
+            )),
+        };
+        let brush = match brushes.is_empty() {
+            true => None,
+            false => Some((
+                instance.get_typed_func(&store, "brush")?,
+                address(&instance, &mut store, "brush_cells")?,
+            )),
+        };
+        Ok(Plugin {
+            name,
+            store: RefCell::new(store),
+            memory,
+            materials,
+            brushes,
+            update,
+            brush,
+            failed: Cell::new(false),
+            out_of_fuel: Cell::new(false),
+            fuel_reported: Cell::new(false),
+            brush_out_of_fuel: Cell::new(false),
+        })
+    }
+
+    /// Fills up every plugin's fuel for the next tick
+    pub fn start_tick(&mut self) {
+        for plugin in &mut self.plugins {
+            plugin.out_of_fuel.set(false);
+            // Only fails if fuel is turned off, which it never is
+            let _ = plugin.store.get_mut().set_fuel(FUEL_PER_TICK);
+        }
+    }
+
+    /// The materials that are updated by a plugin
+    pub fn materials(&self) -> impl Iterator<Item = PixelType> + '_ {
+        self.plugins
+            .iter()
+            .flat_map(|plugin| plugin.materials.iter().copied())
+    }
+
+    /// The names of the brush tools the plugins added
+    pub fn brushes(&self) -> impl Iterator<Item = &str> {
+        self.plugins
+            .iter()
+            .flat_map(|plugin| plugin.brushes.iter().map(String::as_str))
+    }
+
+    /// Asks the plugin of `pixel_type` what the cell at `x`, `y` does this tick.
+    /// Returns None when no plugin registered the material
+    pub fn update(
+        &self,
+        pixel_type: PixelType,
+        chunk: &Chunk,
+        x: i32,
+        y: i32,
+        rng: &RandGenerator,
+    ) -> Option<Option<GridMovement>> {
+        let plugin = self
+            .plugins
+            .iter()
+            .find(|plugin| plugin.materials.contains(&pixel_type))?;
+        let (update, neighbourhood) = plugin.update.as_ref()?;
+        if plugin.failed.get() || plugin.out_of_fuel.get() {
+            return Some(None);
+        }
+        let cells: Vec<u8> = chunk
+            .neighbourhood(x, y, NEIGHBOURHOOD_REACH)
+            .map(|cell| cell.map_or(OUTSIDE, |pixel_type| pixel_type.index() as u8))
+            .collect();
+        let (world_x, world_y) = to_world_position(chunk.key(), (x, y));
+        let random = rng.gen_range(0, i32::MAX);
+
+        let mut store = plugin.store.borrow_mut();
+        let result = plugin
+            .memory
+            .write(&mut *store, *neighbourhood, &cells)
+            .map_err(wasmi::Error::from)
+            .and_then(|()| {
+                update.call(
+                    &mut *store,
+                    (pixel_type.index() as i32, world_x, world_y, random),
+                )
+            });
+        let result = match result {
+            Ok(result) => result,
+            Err(error) => {
+                plugin.trapped(&error);
+                return Some(None);
+            }
+        };
+        Some(decode(result).and_then(|(offset, new_type)| {
+            GridMovement::proposed(chunk, (x, y), offset, pixel_type, new_type)
+        }))
+    }
+
+    /// Runs the brush tool `name`, and returns the cells it paints around the mouse
+    pub fn brush(
+        &self,
+        name: &str,
+        size: i32,
+        random: i32,
+    ) -> Result<Vec<BrushCell>, Box<dyn Error>> {
+        let (plugin, id) = self
+            .plugins
+            .iter()
+            .find_map(|plugin| {
+                let id = plugin.brushes.iter().position(|brush| brush == name)?;
+                Some((plugin, id))
+            })
+            .ok_or_else(|| format!("There is no plugin brush called {name}"))?;
+        let (brush, cells) = plugin.brush.as_ref().ok_or("The plugin has no brush")?;
+        if plugin.failed.get() {
+            return Err(format!("Plugin {} stopped working", plugin.name).into());
+        }
+
+        let mut store = plugin.store.borrow_mut();
+        // Brushes get their own fuel, without eating into the tick's budget
+        let tick_fuel = store.get_fuel().map_err(wasmi::Error::from)?;
+        store.set_fuel(FUEL_PER_BRUSH).map_err(wasmi::Error::from)?;
+        let result = brush.call(&mut *store, (id as i32, size, random));
+        store.set_fuel(tick_fuel).map_err(wasmi::Error::from)?;
+        let count = match result {
+            Ok(count) => (count.max(0) as usize).min(MAX_BRUSH_CELLS),
+            Err(error) if error.as_trap_code() == Some(TrapCode::OutOfFuel) => {
+                // Only report it the first time, a held brush is used every frame
+                if !plugin.brush_out_of_fuel.replace(true) {
+                    println!("Brush {name} of plugin {} ran out of fuel", plugin.name);
+                }
+                return Ok(vec![]);
+            }
+            Err(error) => {
+                drop(store);
+                plugin.trapped(&error);
+                return Err(error.into());
+            }
+        };
+        let mut bytes = vec![0; count * 3];
+        plugin
+            .memory
+            .read(&*store, *cells, &mut bytes)
+            .map_err(wasmi::Error::from)?;
+        Ok(bytes
+            .chunks_exact(3)
+            .filter_map(|cell| {
+                let pixel_type = PixelType::from_index(cell[2] as usize)?;
+                Some(((cell[0] as i8 as i32, cell[1] as i8 as i32), pixel_type))
+            })
+            .collect())
+    }
+}
+
+/// Reads a name the plugin passed to a host function
+fn read_string(caller: &Caller<'_, PluginState>, pointer: i32, length: i32) -> Option<String> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)?;
+    let mut bytes = vec![0; length.clamp(0, 64) as usize];
+    memory
+        .read(caller, pointer as u32 as usize, &mut bytes)
+        .ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Calls an export that tells where in its memory the plugin keeps a buffer
+fn address(
+    instance: &Instance,
+    store: &mut Store<PluginState>,
+    name: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let function = instance.get_typed_func::<(), i32>(&*store, name)?;
+    Ok(function.call(&mut *store, ())? as u32 as usize)
+}
+
+/// Turns what `update` returned into an offset and the material the cell becomes
+fn decode(result: i32) -> Option<((i32, i32), PixelType)> {
+    if result < 0 {
+        return None;
+    }
+    let dx = (result & 0xF) - NEIGHBOURHOOD_REACH;
+    let dy = ((result >> 4) & 0xF) - NEIGHBOURHOOD_REACH;
+    if dx.abs() > NEIGHBOURHOOD_REACH || dy.abs() > NEIGHBOURHOOD_REACH {
+        return None;
+    }
+    let pixel_type = PixelType::from_index((result >> 8) as usize)?;
+    Some(((dx, dy), pixel_type))
+}
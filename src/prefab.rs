@@ -0,0 +1,235 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::selection::Pattern;
+
+/// What a prefab file holds besides the pattern. All of it is optional,
+/// a prefab without a title is shown under its file name
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrefabInfo {
+    pub title: String,
+    pub description: String,
+    pub tags: Vec<String>,
+}
+
+/// The contents of a `<name>.ron` prefab file
+#[derive(Debug, Serialize, Deserialize)]
+struct PrefabFile {
+    #[serde(default)]
+    info: PrefabInfo,
+    pattern: Pattern,
+}
+
+/// A pattern saved under a name, with a small picture of it for the browser
+pub struct Prefab {
+    // The file name without the extension, used to look the prefab up
+    name: String,
+    info: PrefabInfo,
+    pattern: Pattern,
+    thumbnail: Texture2D,
+}
+
+impl Prefab {
+    fn new(name: String, info: PrefabInfo, pattern: Pattern) -> Self {
+        let mut image =
+            Image::gen_image_color(pattern.width() as u16, pattern.height() as u16, BLANK);
+        for y in 0..pattern.height() {
+            for x in 0..pattern.width() {
+                image.set_pixel(x as u32, y as u32, pattern.get(x, y).to_color());
+            }
+        }
+        let thumbnail = Texture2D::from_image(&image);
+        // Set filter mode to nearest to prevent blurry pixels
+        thumbnail.set_filter(FilterMode::Nearest);
+        Self {
+            name,
+            info,
+            pattern,
+            thumbnail,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The title to show in the browser, which is the name unless the file has one
+    pub fn title(&self) -> &str {
+        if self.info.title.is_empty() {
+            &self.name
+        } else {
+            &self.info.title
+        }
+    }
+
+    pub fn info(&self) -> &PrefabInfo {
+        &self.info
+    }
+
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    pub fn thumbnail(&self) -> &Texture2D {
+        &self.thumbnail
+    }
+}
+
+/// All the prefabs in the user's library folder, one `<name>.ron` file per prefab
+pub struct PrefabLibrary {
+    folder: PathBuf,
+    prefabs: Vec<Prefab>,
+}
+
+impl PrefabLibrary {
+    /// Loads every prefab in `folder`. Files that can't be read are reported and skipped
+    pub fn load(folder: PathBuf) -> Self {
+        let mut library = Self {
+            folder,
+            prefabs: vec![],
+        };
+        library.reload();
+        library
+    }
+
+    /// Reads the library folder again, to pick up prefabs that were added or removed by hand
+    pub fn reload(&mut self) {
+        self.prefabs.clear();
+        let Ok(entries) = fs::read_dir(&self.folder) else {
+            // The folder doesn't exist until the first prefab is saved
+            return;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+            .collect();
+        // Keep the browser in the same order every time
+        paths.sort();
+        for path in paths {
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            match read_prefab(&path) {
+                Ok(file) => {
+                    self.prefabs
+                        .push(Prefab::new(name.to_string(), file.info, file.pattern))
+                }
+                Err(error) => println!("Failed to load prefab {}: {error}", path.display()),
+            }
+        }
+    }
+
+    /// Saves `pattern` as `<name>.ron` in the library folder, replacing a prefab with the same name.
+    /// The title, description and tags of the prefab it replaces are kept
+    pub fn save(&mut self, name: &str, pattern: &Pattern) -> Result<(), Box<dyn Error>> {
+        if name.is_empty() || name.contains(['/', '\\', '.']) {
+            return Err(format!("'{name}' can't be used as a prefab name").into());
+        }
+        let file = PrefabFile {
+            info: self
+                .get(name)
+                .map_or_else(PrefabInfo::default, |prefab| prefab.info.clone()),
+            pattern: pattern.clone(),
+        };
+        fs::create_dir_all(&self.folder)?;
+        let text = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default())?;
+        fs::write(self.folder.join(format!("{name}.ron")), text)?;
+
+        let prefab = Prefab::new(name.to_string(), file.info, file.pattern);
+        match self.prefabs.iter_mut().find(|prefab| prefab.name == name) {
+            Some(existing) => *existing = prefab,
+            None => {
+                self.prefabs.push(prefab);
+                self.prefabs.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.iter().find(|prefab| prefab.name == name)
+    }
+
+    pub fn prefabs(&self) -> &[Prefab] {
+        &self.prefabs
+    }
+}
+
+/// Reads a prefab file without making a thumbnail, so it also works without a window
+pub fn read_pattern(path: &Path) -> Result<Pattern, Box<dyn Error>> {
+    Ok(read_prefab(path)?.pattern)
+}
+
+fn read_prefab(path: &Path) -> Result<PrefabFile, Box<dyn Error>> {
+    parse_prefab(&fs::read_to_string(path)?)
+}
+
+fn parse_prefab(text: &str) -> Result<PrefabFile, Box<dyn Error>> {
+    match ron::from_str(text) {
+        Ok(file) => Ok(file),
+        // Prefabs saved before they had a title and tags are just the pattern
+        Err(ron::error::SpannedError {
+            code:
+                ron::Error::MissingStructField {
+                    field: "pattern", ..
+                },
+            ..
+        }) => Ok(PrefabFile {
+            info: PrefabInfo::default(),
+            pattern: ron::from_str(text)?,
+        }),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_prefabs_with_info() {
+        let file = parse_prefab(
+            "(info: (title: \"Tower\", tags: [\"stone\"]), \
+             pattern: (width: 1, height: 2, pixels: [Stone, Sand]))",
+        )
+        .unwrap();
+        assert_eq!(file.info.title, "Tower");
+        assert_eq!(file.info.description, "");
+        assert_eq!(file.info.tags, ["stone"]);
+        assert_eq!((file.pattern.width(), file.pattern.height()), (1, 2));
+    }
+
+    #[test]
+    fn reads_prefabs_without_info() {
+        let file = parse_prefab("(pattern: (width: 1, height: 1, pixels: [Water]))").unwrap();
+        assert_eq!(file.info.title, "");
+        assert_eq!(file.pattern.get(0, 0), crate::pixel::PixelType::Water);
+    }
+
+    #[test]
+    fn falls_back_to_a_bare_legacy_pattern() {
+        let file = parse_prefab("(width: 2, height: 1, pixels: [Sand, Dirt])").unwrap();
+        assert_eq!(file.info.title, "");
+        assert!(file.info.tags.is_empty());
+        assert_eq!(file.pattern.get(1, 0), crate::pixel::PixelType::Dirt);
+    }
+
+    #[test]
+    fn checks_legacy_patterns_too() {
+        let error = parse_prefab("(width: 2, height: 1, pixels: [Sand])").unwrap_err();
+        assert!(error.to_string().contains("needs 2 pixels"), "{error}");
+    }
+
+    #[test]
+    fn reports_other_errors_as_they_are() {
+        let error = parse_prefab("(info: (title: 3), pattern: ())").unwrap_err();
+        assert!(!error.to_string().contains("width"), "{error}");
+        assert!(parse_prefab("not ron").is_err());
+    }
+}
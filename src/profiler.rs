@@ -0,0 +1,131 @@
+use macroquad::prelude::*;
+use std::{collections::VecDeque, error::Error, fs, time::Duration};
+
+use crate::pixel_grid::ChunkGrid;
+
+// How many frames of timings the graphs show
+const HISTORY: usize = 300;
+// How many of the slowest chunks are listed
+const SLOWEST_CHUNKS: usize = 5;
+
+/// The parts a frame is split into for timing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Phase {
+    Input,
+    ChunkUpdate,
+    CrossChunk,
+    RigidBodies,
+    Texture,
+    Draw,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [
+        Phase::Input,
+        Phase::ChunkUpdate,
+        Phase::CrossChunk,
+        Phase::RigidBodies,
+        Phase::Texture,
+        Phase::Draw,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Input => "Input",
+            Phase::ChunkUpdate => "Chunk update",
+            Phase::CrossChunk => "Cross-chunk apply",
+            Phase::RigidBodies => "Rigid bodies",
+            Phase::Texture => "Update texture",
+            Phase::Draw => "Draw",
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Phase::Input => GREEN,
+            Phase::ChunkUpdate => ORANGE,
+            Phase::CrossChunk => MAGENTA,
+            Phase::RigidBodies => BROWN,
+            Phase::Texture => SKYBLUE,
+            Phase::Draw => YELLOW,
+        }
+    }
+}
+
+/// Keeps the time every phase took over the last frames, in milliseconds
+#[derive(Default)]
+pub struct Profiler {
+    history: VecDeque<[f32; Phase::ALL.len()]>,
+    current: [f32; Phase::ALL.len()],
+    slowest_chunks: Vec<((i32, i32), Duration)>,
+}
+
+impl Profiler {
+    /// Adds `duration` to the time `phase` took this frame
+    pub fn record(&mut self, phase: Phase, duration: Duration) {
+        self.current[phase as usize] += duration.as_secs_f32() * 1000.0;
+    }
+
+    /// Records the simulation phases of the tick the grid just did
+    pub fn record_tick(&mut self, chunk_grid: &ChunkGrid) {
+        let mut chunks: Vec<((i32, i32), Duration)> = chunk_grid
+            .chunks()
+            .map(|(key, chunk)| (*key, chunk.update_cost()))
+            .collect();
+        self.record(
+            Phase::ChunkUpdate,
+            chunks.iter().map(|(_, cost)| *cost).sum(),
+        );
+        self.record(Phase::CrossChunk, chunk_grid.cross_chunk_cost());
+        self.record(Phase::RigidBodies, chunk_grid.bodies_cost());
+        chunks.sort_by_key(|(_, cost)| std::cmp::Reverse(*cost));
+        chunks.truncate(SLOWEST_CHUNKS);
+        self.slowest_chunks = chunks;
+    }
+
+    /// Closes the current frame and starts timing the next one
+    pub fn end_frame(&mut self) {
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.current);
+        self.current = [0.0; Phase::ALL.len()];
+    }
+
+    /// The average time `phase` took over the history, in milliseconds
+    pub fn average(&self, phase: Phase) -> f32 {
+        if self.history.is_empty() {
+            return 0.0;
+        }
+        let total: f32 = self.history.iter().map(|frame| frame[phase as usize]).sum();
+        total / self.history.len() as f32
+    }
+
+    /// The time every phase took over the last frames, oldest first, in milliseconds
+    pub fn history(&self) -> &VecDeque<[f32; Phase::ALL.len()]> {
+        &self.history
+    }
+
+    /// The chunks that took the longest to update last tick, slowest first
+    pub fn slowest_chunks(&self) -> &[((i32, i32), Duration)] {
+        &self.slowest_chunks
+    }
+
+    /// Writes the history to a CSV file, one row per frame and one column per phase
+    pub fn export_csv(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut csv = String::from("frame");
+        for phase in Phase::ALL {
+            csv.push_str(&format!(",{} (ms)", phase.as_str()));
+        }
+        csv.push('\n');
+        for (frame, timings) in self.history.iter().enumerate() {
+            csv.push_str(&frame.to_string());
+            for timing in timings {
+                csv.push_str(&format!(",{timing:.4}"));
+            }
+            csv.push('\n');
+        }
+        fs::write(path, csv)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs::File, io::BufWriter};
+
+use crate::{
+    image_io::{region_rgba, write_png},
+    pixel_grid::ChunkGrid,
+};
+
+// APNG frames are kept in memory until the recording stops, so stop taking them past this many bytes
+const APNG_MEMORY_LIMIT: usize = 1 << 30;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum CaptureFormat {
+    Gif,
+    Apng,
+    /// One numbered PNG per frame, next to the given path
+    PngSequence,
+}
+
+impl CaptureFormat {
+    pub const ALL: [CaptureFormat; 3] = [
+        CaptureFormat::Gif,
+        CaptureFormat::Apng,
+        CaptureFormat::PngSequence,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CaptureFormat::Gif => "GIF",
+            CaptureFormat::Apng => "APNG",
+            CaptureFormat::PngSequence => "PNG sequence",
+        }
+    }
+}
+
+/// Captures frames of a region of the world straight from the cell data, every `every` ticks.
+/// Frames don't depend on the window, so the animation plays at `fps` whatever the display did
+pub struct FrameRecorder {
+    format: CaptureFormat,
+    path: String,
+    region: (Vec2, Vec2),
+    every: u64,
+    scale: u32,
+    fps: u32,
+    frames: usize,
+    gif: Option<gif::Encoder<BufWriter<File>>>,
+    // An APNG needs to know how many frames it has before the first one is written,
+    // so its frames are kept until the recording stops, up to APNG_MEMORY_LIMIT
+    apng_frames: Vec<Vec<u8>>,
+    size: (u32, u32),
+}
+
+impl FrameRecorder {
+    /// Starts recording the pixels between the two corners of `region`, both included
+    pub fn start(
+        format: CaptureFormat,
+        path: &str,
+        region: (Vec2, Vec2),
+        every: u64,
+        scale: u32,
+        fps: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let scale = scale.max(1);
+        let size = (region.1 - region.0).abs() + 1.0;
+        let size = match (
+            (size.x as u32).checked_mul(scale),
+            (size.y as u32).checked_mul(scale),
+        ) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err("The region is too big to capture at this scale".into()),
+        };
+        let gif = match format {
+            CaptureFormat::Gif => {
+                if size.0 > u16::MAX as u32 || size.1 > u16::MAX as u32 {
+                    return Err("The region is too big for a GIF".into());
+                }
+                let file = BufWriter::new(File::create(path)?);
+                let mut encoder = gif::Encoder::new(file, size.0 as u16, size.1 as u16, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                Some(encoder)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            format,
+            path: path.to_string(),
+            region,
+            every: every.max(1),
+            scale,
+            fps: fps.max(1),
+            frames: 0,
+            gif,
+            apng_frames: vec![],
+            size,
+        })
+    }
+
+    /// Captures a frame if the grid is on a tick we record
+    pub fn capture(&mut self, chunk_grid: &ChunkGrid) -> Result<(), Box<dyn Error>> {
+        if !chunk_grid.tick().is_multiple_of(self.every) {
+            return Ok(());
+        }
+        let (width, height, mut bytes) =
+            region_rgba(chunk_grid, self.region.0, self.region.1, self.scale)?;
+        match self.format {
+            CaptureFormat::Gif => {
+                if let Some(encoder) = self.gif.as_mut() {
+                    let mut frame =
+                        gif::Frame::from_rgba_speed(width as u16, height as u16, &mut bytes, 10);
+                    // GIF delays are in hundredths of a second
+                    frame.delay = (100 / self.fps).max(1) as u16;
+                    encoder.write_frame(&frame)?;
+                }
+            }
+            CaptureFormat::Apng => {
+                let kept = self.apng_frames.len() * bytes.len();
+                if kept + bytes.len() > APNG_MEMORY_LIMIT {
+                    return Err(format!(
+                        "An APNG is kept in memory until it is finished, and {} frames of {width} by {height} \
+                        is all that fits in {} MiB. Capture a GIF or PNG sequence for longer recordings",
+                        self.apng_frames.len(),
+                        APNG_MEMORY_LIMIT >> 20
+                    )
+                    .into());
+                }
+                self.apng_frames.push(bytes);
+            }
+            CaptureFormat::PngSequence => {
+                write_png(&self.frame_path(self.frames), width, height, &bytes)?
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    /// Finishes the file and returns where the recording was written
+    pub fn finish(mut self) -> Result<String, Box<dyn Error>> {
+        match self.format {
+            // Dropping the encoder writes the end of the file
+            CaptureFormat::Gif => drop(self.gif.take()),
+            CaptureFormat::Apng => {
+                if self.apng_frames.is_empty() {
+                    return Err("No frames were captured".into());
+                }
+                let file = BufWriter::new(File::create(&self.path)?);
+                let mut encoder = png::Encoder::new(file, self.size.0, self.size.1);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(self.apng_frames.len() as u32, 0)?;
+                encoder.set_frame_delay(1, self.fps as u16)?;
+                let mut writer = encoder.write_header()?;
+                for frame in &self.apng_frames {
+                    writer.write_image_data(frame)?;
+                }
+                writer.finish()?;
+            }
+            CaptureFormat::PngSequence => return Ok(format!("{}_*.png", self.stem())),
+        }
+        Ok(self.path)
+    }
+
+    fn stem(&self) -> &str {
+        self.path.strip_suffix(".png").unwrap_or(&self.path)
+    }
+
+    /// `frames/run.png` becomes `frames/run_00042.png` for frame 42
+    fn frame_path(&self, frame: usize) -> String {
+        format!("{}_{frame:05}.png", self.stem())
+    }
+}
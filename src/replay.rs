@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs};
+
+use crate::command::AppCommand;
+
+/// A command together with the tick it was executed on
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    pub tick: u64,
+    pub command: AppCommand,
+}
+
+/// A recorded session. Replaying the commands on a fresh grid with the same seed
+/// gives exactly the same world
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    seed: u64,
+    commands: Vec<ReplayEntry>,
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            commands: vec![],
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn push(&mut self, tick: u64, command: AppCommand) {
+        self.commands.push(ReplayEntry { tick, command });
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+}
+
+/// Plays back a replay by handing out the commands once their tick has come
+pub struct ReplayPlayer {
+    replay: Replay,
+    next: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        Self { replay, next: 0 }
+    }
+
+    /// Returns all commands that should be executed before simulating `tick`
+    pub fn due_commands(&mut self, tick: u64) -> Vec<AppCommand> {
+        let mut commands = vec![];
+        while let Some(entry) = self.replay.commands.get(self.next) {
+            if entry.tick > tick {
+                break;
+            }
+            commands.push(entry.command.clone());
+            self.next += 1;
+        }
+        commands
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.replay.len()
+    }
+
+    pub fn progress(&self) -> (usize, usize) {
+        (self.next, self.replay.len())
+    }
+}
@@ -0,0 +1,482 @@
+//! Rigid bodies: regions of Stone that lost their support fall, tip over and collide as one piece,
+//! instead of floating where they were cut loose.
+//!
+//! Every few ticks the grid looks for connected regions of Stone that nothing holds up: they don't
+//! touch the edge of the world or another solid, and have no powder right below them. Each of those
+//! becomes a body. A body keeps its shape, is moved and turned as a whole, and is drawn back into the
+//! grid as Stone every tick, so everything else still sees normal cells. Water in its way is pushed
+//! aside, and so is sand when the body hits it hard enough. A body that lies still for a while turns
+//! back into normal cells. Bodies are part of snapshots and saves, so a rewind or a load carries on
+//! moving them exactly as before.
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    pixel::{Category, PixelType},
+    pixel_grid::ChunkGrid,
+};
+
+/// The material that forms bodies when it comes loose
+pub const BODY_MATERIAL: PixelType = PixelType::Stone;
+/// How many ticks there are between looking for new bodies
+pub const DETECT_EVERY: u64 = 10;
+
+// In pixels per tick, and radians per tick for spinning
+const GRAVITY: f32 = 0.15;
+const MAX_SPEED: f32 = 4.0;
+const MAX_SPIN: f32 = 0.1;
+// How much of its speed a body keeps when it bounces or slides
+const BOUNCE: f32 = 0.2;
+const FRICTION: f32 = 0.8;
+// Sand only gives way to bodies moving at least this fast, slower ones rest on it
+const PLOUGH_SPEED: f32 = 1.5;
+// How much pushing its whole size worth of water or sand aside slows a body down.
+// Sand stops a body after a short way, water only slows it
+const WATER_DRAG: f32 = 0.5;
+const SAND_DRAG: f32 = 2.0;
+// How many ticks a body has to lie still before it turns back into cells
+const REST_TICKS: u32 = 20;
+// How many cells are searched for a free spot to push a cell to. Cells that find none are lost
+const PUSH_SEARCH: usize = 4096;
+
+/// A body as it is written in a save
+#[derive(Serialize, Deserialize)]
+pub struct BodyState {
+    width: i32,
+    height: i32,
+    shape: Vec<bool>,
+    pivot: (f32, f32),
+    position: (f32, f32),
+    angle: f32,
+    velocity: (f32, f32),
+    spin: f32,
+    cells: Vec<(i32, i32)>,
+    resting: u32,
+}
+
+/// How far the furthest cell of a shape is from its centre of mass, and how hard it is to spin.
+/// Worked out the same way for new and loaded bodies, so a loaded body moves exactly like it did
+fn spread(width: i32, shape: &[bool], pivot: Vec2) -> (f32, f32) {
+    let distances: Vec<f32> = shape
+        .iter()
+        .enumerate()
+        .filter(|(_, filled)| **filled)
+        .map(|(index, _)| {
+            let cell = vec2((index as i32 % width) as f32, (index as i32 / width) as f32);
+            (cell + 0.5 - pivot).length()
+        })
+        .collect();
+    let radius = distances.iter().copied().fold(0.0, f32::max);
+    let inertia = distances
+        .iter()
+        .map(|distance| distance * distance)
+        .sum::<f32>()
+        / distances.len() as f32;
+    (radius, inertia.max(1.0))
+}
+
+/// A loose piece of solid, moving as one
+#[derive(Clone)]
+pub struct RigidBody {
+    // The shape at angle zero, row by row
+    width: i32,
+    height: i32,
+    shape: Vec<bool>,
+    // The centre of mass in the shape, and in the world
+    pivot: Vec2,
+    position: Vec2,
+    angle: f32,
+    velocity: Vec2,
+    spin: f32,
+    // How far the furthest cell is from the centre of mass, and the average squared distance,
+    // which is how hard the body is to spin
+    radius: f32,
+    inertia: f32,
+    // The world cells the body was last drawn into
+    cells: Vec<(i32, i32)>,
+    // How many ticks the body has been lying still
+    resting: u32,
+}
+
+impl RigidBody {
+    /// A body at rest made from world cells
+    fn from_region(region: Vec<(i32, i32)>) -> Self {
+        let min_x = region.iter().map(|cell| cell.0).min().unwrap_or_default();
+        let min_y = region.iter().map(|cell| cell.1).min().unwrap_or_default();
+        let width = region.iter().map(|cell| cell.0).max().unwrap_or_default() - min_x + 1;
+        let height = region.iter().map(|cell| cell.1).max().unwrap_or_default() - min_y + 1;
+        let mut shape = vec![false; (width * height) as usize];
+        let mut pivot = Vec2::ZERO;
+        for (x, y) in &region {
+            shape[((y - min_y) * width + x - min_x) as usize] = true;
+            pivot += vec2((x - min_x) as f32, (y - min_y) as f32) + 0.5;
+        }
+        pivot /= region.len() as f32;
+        let (radius, inertia) = spread(width, &shape, pivot);
+        Self {
+            width,
+            height,
+            shape,
+            pivot,
+            position: vec2(min_x as f32, min_y as f32) + pivot,
+            angle: 0.0,
+            velocity: Vec2::ZERO,
+            spin: 0.0,
+            radius,
+            inertia,
+            cells: region,
+            resting: 0,
+        }
+    }
+
+    /// Everything about the body that changes while it moves, to store in a save
+    pub fn state(&self) -> BodyState {
+        BodyState {
+            width: self.width,
+            height: self.height,
+            shape: self.shape.clone(),
+            pivot: self.pivot.into(),
+            position: self.position.into(),
+            angle: self.angle,
+            velocity: self.velocity.into(),
+            spin: self.spin,
+            cells: self.cells.clone(),
+            resting: self.resting,
+        }
+    }
+
+    /// The body a save stored. Fails if its shape doesn't fit its size
+    pub fn from_state(state: BodyState) -> Result<Self, String> {
+        if state.width <= 0
+            || state.height <= 0
+            || state.shape.len() != (state.width * state.height) as usize
+            || !state.shape.contains(&true)
+        {
+            return Err(format!(
+                "a body of {} by {} can't have a shape of {} cells",
+                state.width,
+                state.height,
+                state.shape.len()
+            ));
+        }
+        let pivot = state.pivot.into();
+        let (radius, inertia) = spread(state.width, &state.shape, pivot);
+        Ok(Self {
+            width: state.width,
+            height: state.height,
+            shape: state.shape,
+            pivot,
+            position: state.position.into(),
+            angle: state.angle,
+            velocity: state.velocity.into(),
+            spin: state.spin,
+            radius,
+            inertia,
+            cells: state.cells,
+            resting: state.resting,
+        })
+    }
+
+    /// The world cells the body is drawn into
+    pub fn cells(&self) -> &[(i32, i32)] {
+        &self.cells
+    }
+
+    /// The centre of mass in world pixels
+    pub fn position(&self) -> Vec2 {
+        self.position
+    }
+
+    pub fn angle(&self) -> f32 {
+        self.angle
+    }
+
+    /// Moves the body one tick. Returns false once it should go back to being normal cells,
+    /// because it came to rest or something else changed its cells
+    pub fn update(&mut self, chunk_grid: &mut ChunkGrid) -> bool {
+        if !self.lift(chunk_grid) {
+            return false;
+        }
+        self.step(chunk_grid);
+        self.place(chunk_grid);
+        self.resting < REST_TICKS
+    }
+
+    /// Takes the body out of the grid, so it doesn't collide with itself.
+    /// Returns false, and leaves the grid alone, if one of its cells was changed by something else
+    fn lift(&mut self, chunk_grid: &mut ChunkGrid) -> bool {
+        if self
+            .cells
+            .iter()
+            .any(|cell| cell_at(chunk_grid, *cell) != Some(BODY_MATERIAL))
+        {
+            return false;
+        }
+        for cell in &self.cells {
+            set_cell(chunk_grid, *cell, PixelType::Air);
+        }
+        true
+    }
+
+    /// Applies gravity and moves and turns the body as far as it can go
+    fn step(&mut self, chunk_grid: &ChunkGrid) {
+        self.velocity.y = (self.velocity.y + GRAVITY).min(MAX_SPEED);
+        self.velocity.x = self.velocity.x.clamp(-MAX_SPEED, MAX_SPEED);
+        self.spin = self.spin.clamp(-MAX_SPIN, MAX_SPIN);
+        let speed = self.velocity.length();
+        // Nothing moves more than a pixel per step, so bodies can't pass through thin walls
+        let steps = self
+            .velocity
+            .abs()
+            .max_element()
+            .max(self.spin.abs() * self.radius)
+            .ceil()
+            .max(1.0);
+        let mut grounded = false;
+        for _ in 0..steps as usize {
+            let fall = vec2(0.0, self.velocity.y / steps);
+            if let Err(contacts) = self.try_move(chunk_grid, fall, 0.0, speed) {
+                if self.velocity.y > 0.0 {
+                    grounded = true;
+                    self.tip(&contacts);
+                    self.velocity.x *= FRICTION;
+                }
+                self.velocity.y = match self.velocity.y.abs() > 1.0 {
+                    true => -self.velocity.y * BOUNCE,
+                    false => 0.0,
+                };
+            }
+            let slide = vec2(self.velocity.x / steps, 0.0);
+            if self.try_move(chunk_grid, slide, 0.0, speed).is_err() {
+                self.velocity.x *= -BOUNCE;
+            }
+            if self.spin != 0.0
+                && self
+                    .try_move(chunk_grid, Vec2::ZERO, self.spin / steps, speed)
+                    .is_err()
+            {
+                self.spin = 0.0;
+            }
+        }
+        let still = self.velocity.length() < GRAVITY * 2.0 && self.spin.abs() < 0.001;
+        self.resting = match grounded && still {
+            true => self.resting + 1,
+            false => 0,
+        };
+    }
+
+    /// Spins the body when its centre of mass hangs past the cells it landed on, so it tips over
+    /// the edge. A body with its centre of mass above what holds it up only slows its spin down
+    fn tip(&mut self, contacts: &[(i32, i32)]) {
+        let left = contacts.iter().map(|cell| cell.0).min().unwrap_or_default() as f32;
+        let right = contacts.iter().map(|cell| cell.0).max().unwrap_or_default() as f32 + 1.0;
+        let lever = self.position.x - self.position.x.clamp(left, right);
+        if lever == 0.0 {
+            self.spin *= FRICTION;
+        } else {
+            // Torque over moment of inertia, with the mass on both sides cancelling out
+            self.spin += GRAVITY * lever / self.inertia;
+        }
+    }
+
+    /// Moves and turns the body if nothing is in the way there, or returns the cells that are
+    fn try_move(
+        &mut self,
+        chunk_grid: &ChunkGrid,
+        offset: Vec2,
+        turn: f32,
+        speed: f32,
+    ) -> Result<(), Vec<(i32, i32)>> {
+        let (position, angle) = (self.position + offset, self.angle + turn);
+        let blocked: Vec<(i32, i32)> = self
+            .rasterise(position, angle)
+            .into_iter()
+            .filter(|cell| blocks(cell_at(chunk_grid, *cell), speed))
+            .collect();
+        if !blocked.is_empty() {
+            return Err(blocked);
+        }
+        self.position = position;
+        self.angle = angle;
+        Ok(())
+    }
+
+    /// Draws the body back into the grid, pushing aside anything loose that is where it lands
+    fn place(&mut self, chunk_grid: &mut ChunkGrid) {
+        self.cells = self.rasterise(self.position, self.angle);
+        let body: HashSet<(i32, i32)> = self.cells.iter().copied().collect();
+        let mut drag = 0.0;
+        for cell in &self.cells {
+            if let Some(pixel_type) = cell_at(chunk_grid, *cell)
+                && pixel_type != PixelType::Air
+            {
+                push_aside(chunk_grid, *cell, pixel_type, &body);
+                drag += match pixel_type.category() {
+                    Category::Powder => SAND_DRAG,
+                    _ => WATER_DRAG,
+                };
+            }
+            set_cell(chunk_grid, *cell, BODY_MATERIAL);
+        }
+        if drag > 0.0 {
+            self.velocity *= 1.0 - (drag / self.cells.len() as f32).min(1.0);
+        }
+    }
+
+    /// The world cells the body covers at a position and angle. Each cell looks up which part of
+    /// the shape its centre falls on, so turning the body leaves no holes
+    fn rasterise(&self, position: Vec2, angle: f32) -> Vec<(i32, i32)> {
+        let rotation = Vec2::from_angle(angle);
+        let inverse = Vec2::from_angle(-angle);
+        let corners = [
+            vec2(0.0, 0.0),
+            vec2(self.width as f32, 0.0),
+            vec2(0.0, self.height as f32),
+            vec2(self.width as f32, self.height as f32),
+        ]
+        .map(|corner| position + rotation.rotate(corner - self.pivot));
+        let min = corners
+            .into_iter()
+            .reduce(Vec2::min)
+            .unwrap_or_default()
+            .floor();
+        let max = corners
+            .into_iter()
+            .reduce(Vec2::max)
+            .unwrap_or_default()
+            .floor();
+        let mut cells = vec![];
+        for y in min.y as i32..=max.y as i32 {
+            for x in min.x as i32..=max.x as i32 {
+                let centre = vec2(x as f32, y as f32) + 0.5;
+                let local = (inverse.rotate(centre - position) + self.pivot).floor();
+                let (local_x, local_y) = (local.x as i32, local.y as i32);
+                if (0..self.width).contains(&local_x)
+                    && (0..self.height).contains(&local_y)
+                    && self.shape[(local_y * self.width + local_x) as usize]
+                {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+}
+
+/// Whether a cell stops a body moving at `speed`. Outside the world counts as a wall
+fn blocks(pixel_type: Option<PixelType>, speed: f32) -> bool {
+    match pixel_type {
+        None => true,
+        Some(PixelType::Air) => false,
+        Some(pixel_type) => match pixel_type.category() {
+            Category::Liquid => false,
+            Category::Powder => speed < PLOUGH_SPEED,
+            _ => true,
+        },
+    }
+}
+
+/// Moves the loose pixel at `cell` to the closest free cell it can flow to, going up first.
+/// Cells in `body` are where the body is about to be drawn
+fn push_aside(
+    chunk_grid: &mut ChunkGrid,
+    cell: (i32, i32),
+    pixel_type: PixelType,
+    body: &HashSet<(i32, i32)>,
+) {
+    let mut queue = VecDeque::from([cell]);
+    let mut seen = HashSet::from([cell]);
+    while let Some((x, y)) = queue.pop_front() {
+        if seen.len() > PUSH_SEARCH {
+            return;
+        }
+        for next in [(x, y - 1), (x - 1, y), (x + 1, y), (x, y + 1)] {
+            if !seen.insert(next) {
+                continue;
+            }
+            // The pixel can go through where the body lands, but not stay there
+            if body.contains(&next) {
+                queue.push_back(next);
+                continue;
+            }
+            match cell_at(chunk_grid, next) {
+                Some(PixelType::Air) => {
+                    set_cell(chunk_grid, next, pixel_type);
+                    return;
+                }
+                Some(other) if !blocks(Some(other), f32::INFINITY) => queue.push_back(next),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Finds the regions of `BODY_MATERIAL` that nothing holds up, and turns them into bodies.
+/// Cells that already belong to one of `bodies` are left out
+pub fn find_bodies(chunk_grid: &ChunkGrid, bodies: &[RigidBody]) -> Vec<RigidBody> {
+    let Some((min, max)) = chunk_grid.bounds() else {
+        return vec![];
+    };
+    let taken: HashSet<(i32, i32)> = bodies
+        .iter()
+        .flat_map(|body| body.cells.iter().copied())
+        .collect();
+    let (left, top) = (min.x as i32, min.y as i32);
+    let width = max.x as i32 - left + 1;
+    let mut visited = vec![false; (width * (max.y as i32 - top + 1)) as usize];
+    let index = |(x, y): (i32, i32)| ((y - top) * width + x - left) as usize;
+    let mut found = vec![];
+    for y in top..=max.y as i32 {
+        for x in left..=max.x as i32 {
+            if visited[index((x, y))]
+                || taken.contains(&(x, y))
+                || cell_at(chunk_grid, (x, y)) != Some(BODY_MATERIAL)
+            {
+                continue;
+            }
+            // Flood fill the region, and note anything that holds it up on the way
+            visited[index((x, y))] = true;
+            let mut region = vec![(x, y)];
+            let mut supported = false;
+            let mut next = 0;
+            while let Some(&(x, y)) = region.get(next) {
+                next += 1;
+                for (neighbour, below) in [
+                    ((x, y - 1), false),
+                    ((x - 1, y), false),
+                    ((x + 1, y), false),
+                    ((x, y + 1), true),
+                ] {
+                    match cell_at(chunk_grid, neighbour) {
+                        // The edge of the world
+                        None => supported = true,
+                        Some(BODY_MATERIAL) if !taken.contains(&neighbour) => {
+                            if !visited[index(neighbour)] {
+                                visited[index(neighbour)] = true;
+                                region.push(neighbour);
+                            }
+                        }
+                        Some(pixel_type) => match pixel_type.category() {
+                            Category::Solid => supported |= !taken.contains(&neighbour),
+                            Category::Powder => supported |= below,
+                            _ => {}
+                        },
+                    }
+                }
+            }
+            if !supported {
+                found.push(RigidBody::from_region(region));
+            }
+        }
+    }
+    found
+}
+
+fn cell_at(chunk_grid: &ChunkGrid, (x, y): (i32, i32)) -> Option<PixelType> {
+    chunk_grid.get_pixel(vec2(x as f32, y as f32))
+}
+
+fn set_cell(chunk_grid: &mut ChunkGrid, (x, y): (i32, i32), pixel_type: PixelType) {
+    chunk_grid.set_pixel(vec2(x as f32, y as f32), pixel_type);
+}
@@ -0,0 +1,783 @@
+//! Cellular automaton rules: 3x3 patterns that rewrite the cells they match, with a chance.
+//!
+//! Every `.rules` file in the rules folder is loaded, in name order. A file names materials and
+//! classes of materials with one character each, and then lists rules:
+//!
+//! ```text
+//! symbol S = Sand
+//! symbol ~ = Water
+//! symbol L = Grass
+//! symbol _ = Air
+//!
+//! # Sand sinks through water half of the time, by swapping places with the water diagonally below
+//! rule sand_sinks
+//! chance 0.5
+//! mirror
+//! ***  ***
+//! *S*  *9*
+//! **~  **5
+//!
+//! # Game of Life with Grass as living cells
+//! rule birth
+//! neighbours L 3
+//! ***  ***
+//! *_*  *L*
+//! ***  ***
+//!
+//! rule death
+//! neighbours L 0,1,4,5,6,7,8
+//! ***  ***
+//! *L*  *_*
+//! ***  ***
+//! ```
+//!
+//! A rule is three rows of pattern and result, side by side, with an optional `->` between them.
+//! In a pattern `*` matches anything, even the cells outside the world, and a symbol matches its
+//! materials. `Outside` can be used in a symbol for the cells where no chunk is loaded. In a result `*`
+//! keeps the cell, a symbol of one material turns the cell into it, and a digit copies the cell at
+//! that place in the pattern: 1 to 9 in reading order, 5 is the middle. Each rule can have these
+//! options before its rows:
+//!
+//! - `chance P`: how likely the rule is to happen when it matches, 1 if not set
+//! - `rotate`: the rule also matches turned by a quarter, a half and three quarters
+//! - `mirror`: the rule also matches flipped left to right
+//! - `neighbours C N,N`: only match when this many of the 8 cells around the middle match `C`
+//!
+//! Rules are tried for every cell, in the order they are in the files. The first one that matches
+//! and passes its chance rewrites the cells, and the cells it changes skip their material's update.
+//! Patterns are matched against the grid as it was at the start of the tick, so rules like the
+//! Game of Life change every cell at once. Patterns look across chunk borders the same as inside
+//! a chunk, but rules never write outside the world.
+//!
+//! Symbols can name the materials that scripts and plugins add, they are loaded before the rules.
+use macroquad::rand::RandGenerator;
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use crate::{
+    pixel::{MATERIAL_COUNT, PixelType},
+    pixel_grid::{ChunkGrid, to_world_position},
+};
+
+// A cell of a neighbourhood is one of the materials, or outside the world
+const CELL_BITS: usize = MATERIAL_COUNT + 1;
+const OUTSIDE: usize = MATERIAL_COUNT;
+const SET_WORDS: usize = CELL_BITS.div_ceil(64);
+const MIDDLE: usize = 4;
+
+/// The materials a pattern cell accepts, a bit per `PixelType::index()` and one for `OUTSIDE`.
+/// It grows with the list of materials, so there can be as many of them as we like
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct MaterialSet([u64; SET_WORDS]);
+
+impl MaterialSet {
+    const EMPTY: MaterialSet = MaterialSet([0; SET_WORDS]);
+
+    fn any() -> Self {
+        let mut set = Self::EMPTY;
+        for bit in 0..CELL_BITS {
+            set.insert(bit);
+        }
+        set
+    }
+
+    fn single(bit: usize) -> Self {
+        let mut set = Self::EMPTY;
+        set.insert(bit);
+        set
+    }
+
+    fn insert(&mut self, bit: usize) {
+        self.0[bit / 64] |= 1 << (bit % 64);
+    }
+
+    fn remove(&mut self, bit: usize) {
+        self.0[bit / 64] &= !(1 << (bit % 64));
+    }
+
+    fn contains(&self, bit: usize) -> bool {
+        self.0[bit / 64] & 1 << (bit % 64) != 0
+    }
+}
+
+/// What a rule does to one cell of the 3x3
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Write {
+    Keep,
+    Set(PixelType),
+    // Copies the cell at this index of the pattern
+    Copy(usize),
+}
+
+/// A rule as written in a file
+struct Rule {
+    name: String,
+    chance: f32,
+    rotate: bool,
+    mirror: bool,
+    // The cells around the middle to count, and the counts that match as bits
+    neighbours: Option<(MaterialSet, u16)>,
+    pattern: [MaterialSet; 9],
+    result: [Write; 9],
+}
+
+impl Rule {
+    /// The rule and its turned and flipped versions, without duplicates
+    fn variants(&self) -> Vec<Variant> {
+        let turns = if self.rotate { 4 } else { 1 };
+        let flips = if self.mirror { 2 } else { 1 };
+        let mut variants: Vec<Variant> = vec![];
+        for flip in 0..flips {
+            for turn in 0..turns {
+                let variant = self.transformed(|index| {
+                    let (mut x, mut y) = (index as i32 % 3 - 1, index as i32 / 3 - 1);
+                    if flip == 1 {
+                        x = -x;
+                    }
+                    for _ in 0..turn {
+                        (x, y) = (-y, x);
+                    }
+                    ((y + 1) * 3 + x + 1) as usize
+                });
+                if !variants.contains(&variant) {
+                    variants.push(variant);
+                }
+            }
+        }
+        variants
+    }
+
+    /// Compiles the rule with every cell moved to `place(index)`
+    fn transformed(&self, place: impl Fn(usize) -> usize) -> Variant {
+        let mut pattern = [MaterialSet::any(); 9];
+        let mut result = [Write::Keep; 9];
+        for index in 0..9 {
+            pattern[place(index)] = self.pattern[index];
+            result[place(index)] = match self.result[index] {
+                Write::Copy(from) => Write::Copy(place(from)),
+                write => write,
+            };
+        }
+        for index in 0..9 {
+            // Copying a cell onto itself changes nothing
+            if result[index] == Write::Copy(index) {
+                result[index] = Write::Keep;
+            }
+            // Cells that are written or copied have to be inside the world
+            match result[index] {
+                Write::Keep => {}
+                Write::Set(_) => pattern[index].remove(OUTSIDE),
+                Write::Copy(from) => {
+                    pattern[index].remove(OUTSIDE);
+                    pattern[from].remove(OUTSIDE);
+                }
+            }
+        }
+        // Without the option zero cells of nothing are counted, and zero is allowed
+        let (counted, counts) = self.neighbours.unwrap_or((MaterialSet::EMPTY, 1));
+        Variant {
+            pattern,
+            counted,
+            counts,
+            result,
+        }
+    }
+}
+
+/// A compiled rule, in one orientation
+#[derive(PartialEq, Debug)]
+struct Variant {
+    pattern: [MaterialSet; 9],
+    // The cells around the middle that are counted, and the counts that match as bits
+    counted: MaterialSet,
+    counts: u16,
+    result: [Write; 9],
+}
+
+impl Variant {
+    /// Whether the rule matches a neighbourhood, given as the bit of every cell's material or `OUTSIDE`
+    fn matches(&self, neighbourhood: &[usize; 9]) -> bool {
+        let fits = neighbourhood
+            .iter()
+            .zip(&self.pattern)
+            .all(|(bit, set)| set.contains(*bit));
+        let count = neighbourhood
+            .iter()
+            .enumerate()
+            .filter(|(index, bit)| *index != MIDDLE && self.counted.contains(**bit))
+            .count();
+        fits && self.counts & 1 << count != 0
+    }
+}
+
+/// A rule with all its variants. Only one variant is used per cell, and the chance is rolled once
+struct RuleGroup {
+    chance: f32,
+    variants: Vec<Variant>,
+}
+
+/// What a rule does to the cells around `x`, `y`. Every cell it touches holds the material that was
+/// seen there and the one it becomes, so it can be skipped if another rewrite got there first
+pub struct Rewrite {
+    x: i32,
+    y: i32,
+    cells: [Option<(PixelType, PixelType)>; 9],
+}
+
+impl Rewrite {
+    /// The world positions this rewrite touches, with what was seen there and what it becomes
+    pub fn cells(&self) -> impl Iterator<Item = ((i32, i32), PixelType, PixelType)> + '_ {
+        self.cells.iter().enumerate().filter_map(|(index, cell)| {
+            let (seen, new) = (*cell)?;
+            let position = (self.x + index as i32 % 3 - 1, self.y + index as i32 / 3 - 1);
+            Some((position, seen, new))
+        })
+    }
+}
+
+/// The rules from the rules folder, compiled and sorted by the material in the middle of their pattern
+pub struct RuleSet {
+    names: Vec<String>,
+    by_middle: [Vec<RuleGroup>; MATERIAL_COUNT],
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self {
+            names: vec![],
+            by_middle: std::array::from_fn(|_| vec![]),
+        }
+    }
+}
+
+impl RuleSet {
+    /// Compiles every rules file in `folder`. Files with mistakes are reported and skipped
+    pub fn load(folder: &Path) -> Self {
+        let mut rule_set = Self::default();
+        let Ok(entries) = fs::read_dir(folder) else {
+            // No rules folder means there are no rules
+            return rule_set;
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "rules")
+            })
+            .collect();
+        paths.sort();
+        for path in paths {
+            match fs::read_to_string(&path)
+                .map_err(Box::<dyn Error>::from)
+                .and_then(|text| Ok(parse(&text)?))
+            {
+                Ok(rules) => {
+                    println!("Loaded {} rules from {}", rules.len(), path.display());
+                    for rule in rules {
+                        rule_set.add(rule);
+                    }
+                }
+                Err(error) => println!("Failed to load rules {}: {error}", path.display()),
+            }
+        }
+        rule_set
+    }
+
+    fn add(&mut self, rule: Rule) {
+        let middle = rule.pattern[MIDDLE];
+        let group = || RuleGroup {
+            chance: rule.chance,
+            variants: rule.variants(),
+        };
+        for (index, groups) in self.by_middle.iter_mut().enumerate() {
+            if middle.contains(index) {
+                groups.push(group());
+            }
+        }
+        self.names.push(rule.name);
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// What the first rule that matches around `x`, `y` in the chunk at `key` and passes its chance
+    /// does there, if any. The neighbourhood is read from the whole grid, so it reaches into the
+    /// chunks around
+    pub fn rewrite(
+        &self,
+        chunk_grid: &ChunkGrid,
+        key: (i32, i32),
+        (x, y): (i32, i32),
+        rng: &RandGenerator,
+    ) -> Option<Rewrite> {
+        let middle = *chunk_grid.chunk(key)?.get(x, y)?;
+        let groups = &self.by_middle[middle.index()];
+        if groups.is_empty() {
+            return None;
+        }
+        let cells = chunk_grid.neighbourhood(key, (x, y));
+        let neighbourhood = cells.map(|cell| cell.map_or(OUTSIDE, PixelType::index));
+        for group in groups {
+            // Start at a random variant, so mirrored rules don't always go the same way
+            let start = match group.variants.len() {
+                1 => 0,
+                count => rng.gen_range(0, count),
+            };
+            let Some(variant) = (0..group.variants.len())
+                .map(|offset| &group.variants[(start + offset) % group.variants.len()])
+                .find(|variant| variant.matches(&neighbourhood))
+            else {
+                continue;
+            };
+            if group.chance < 1.0 && rng.gen_range(0.0, 1.0) >= group.chance {
+                continue;
+            }
+            let (x, y) = to_world_position(key, (x, y));
+            return Some(Self::rewrite_cells(x, y, &cells, &variant.result));
+        }
+        None
+    }
+
+    fn rewrite_cells(
+        x: i32,
+        y: i32,
+        cells: &[Option<PixelType>; 9],
+        result: &[Write; 9],
+    ) -> Rewrite {
+        let mut rewrite = Rewrite {
+            x,
+            y,
+            cells: [None; 9],
+        };
+        // The pattern makes sure every cell that is written or copied is inside the world
+        let seen = |index: usize| cells[index].unwrap_or(PixelType::Air);
+        for (index, write) in result.iter().enumerate() {
+            match *write {
+                Write::Keep => {}
+                Write::Set(pixel_type) => rewrite.cells[index] = Some((seen(index), pixel_type)),
+                Write::Copy(from) => {
+                    rewrite.cells[index] = Some((seen(index), seen(from)));
+                    // The copied cell has to still be there when the rewrite is applied
+                    rewrite.cells[from].get_or_insert((seen(from), seen(from)));
+                }
+            }
+        }
+        rewrite
+    }
+}
+
+/// Reads the rules in a rules file, or says on which line it went wrong
+fn parse(text: &str) -> Result<Vec<Rule>, String> {
+    let mut symbols: HashMap<char, MaterialSet> = HashMap::from([('*', MaterialSet::any())]);
+    let mut rules: Vec<Rule> = vec![];
+    // The rows of the rule being read, it is done after three
+    let mut rows: Vec<(String, String)> = vec![];
+    // Whether the last rule has all of its rows
+    let mut has_rows = true;
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: String| format!("line {}: {message}", number + 1);
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["symbol", symbol, "=", ..] => {
+                let mut characters = symbol.chars();
+                let (Some(character), None) = (characters.next(), characters.next()) else {
+                    return Err(error(format!("'{symbol}' should be a single character")));
+                };
+                if character == '*' || character.is_ascii_digit() {
+                    return Err(error(format!("'{character}' can't be used as a symbol")));
+                }
+                let mut set = MaterialSet::EMPTY;
+                for name in line.split('=').nth(1).unwrap_or_default().split('|') {
+                    set.insert(material_bit(name.trim()).map_err(error)?);
+                }
+                symbols.insert(character, set);
+            }
+            ["rule", name] => {
+                if !has_rows {
+                    return Err(error(format!(
+                        "the rule before this one has {} rows, it needs 3",
+                        rows.len()
+                    )));
+                }
+                has_rows = false;
+                rules.push(Rule {
+                    name: name.to_string(),
+                    chance: 1.0,
+                    rotate: false,
+                    mirror: false,
+                    neighbours: None,
+                    pattern: [MaterialSet::any(); 9],
+                    result: [Write::Keep; 9],
+                });
+            }
+            _ => {
+                let Some(rule) = rules.last_mut() else {
+                    return Err(error(String::from("expected a symbol or a rule")));
+                };
+                let is_option = matches!(
+                    words[..],
+                    ["chance", _] | ["rotate"] | ["mirror"] | ["neighbours", _, _]
+                );
+                if is_option && (has_rows || !rows.is_empty()) {
+                    return Err(error(format!(
+                        "'{line}' has to come before the rows of rule {}",
+                        rule.name
+                    )));
+                }
+                match words[..] {
+                    ["chance", chance] => {
+                        rule.chance = chance
+                            .parse::<f32>()
+                            .ok()
+                            .filter(|chance| (0.0..=1.0).contains(chance))
+                            .ok_or_else(|| {
+                                error(format!("'{chance}' is not a chance from 0 to 1"))
+                            })?;
+                    }
+                    ["rotate"] => rule.rotate = true,
+                    ["mirror"] => rule.mirror = true,
+                    ["neighbours", symbol, counts] => {
+                        let class = symbol
+                            .chars()
+                            .next()
+                            .and_then(|character| symbols.get(&character))
+                            .ok_or_else(|| error(format!("Unknown symbol '{symbol}'")))?;
+                        let mut allowed = 0;
+                        for count in counts.split(',') {
+                            let count = count
+                                .parse::<u16>()
+                                .ok()
+                                .filter(|count| *count <= 8)
+                                .ok_or_else(|| {
+                                    error(format!("'{count}' is not a count from 0 to 8"))
+                                })?;
+                            allowed |= 1 << count;
+                        }
+                        rule.neighbours = Some((*class, allowed));
+                    }
+                    _ => {
+                        let cells: Vec<&str> =
+                            words.into_iter().filter(|word| *word != "->").collect();
+                        let [pattern, result] = cells[..] else {
+                            return Err(error(format!(
+                                "expected an option or a row of pattern and result, not '{line}'"
+                            )));
+                        };
+                        if pattern.chars().count() != 3 || result.chars().count() != 3 {
+                            return Err(error(String::from("rows are 3 cells wide")));
+                        }
+                        if has_rows {
+                            return Err(error(format!(
+                                "rule {} already has its 3 rows",
+                                rule.name
+                            )));
+                        }
+                        rows.push((pattern.to_string(), result.to_string()));
+                        if rows.len() == 3 {
+                            fill_rows(rule, &rows, &symbols).map_err(error)?;
+                            rows.clear();
+                            has_rows = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if !has_rows {
+        return Err(String::from("the last rule needs 3 rows"));
+    }
+    Ok(rules)
+}
+
+/// Reads the symbols of three rows into a rule's pattern and result
+fn fill_rows(
+    rule: &mut Rule,
+    rows: &[(String, String)],
+    symbols: &HashMap<char, MaterialSet>,
+) -> Result<(), String> {
+    let patterns = rows.iter().flat_map(|(pattern, _)| pattern.chars());
+    let results = rows.iter().flat_map(|(_, result)| result.chars());
+    for (index, (pattern, result)) in patterns.zip(results).enumerate() {
+        rule.pattern[index] = *symbols
+            .get(&pattern)
+            .ok_or_else(|| format!("Unknown symbol '{pattern}'"))?;
+        rule.result[index] = match result {
+            '*' => Write::Keep,
+            '1'..='9' => Write::Copy(result as usize - '1' as usize),
+            _ => {
+                let set = *symbols
+                    .get(&result)
+                    .ok_or_else(|| format!("Unknown symbol '{result}'"))?;
+                (0..MATERIAL_COUNT)
+                    .find(|index| set == MaterialSet::single(*index))
+                    .and_then(PixelType::from_index)
+                    .map(Write::Set)
+                    .ok_or_else(|| format!("'{result}' has to be a single material in a result"))?
+            }
+        };
+    }
+    Ok(())
+}
+
+/// The bit of a material name, or of `Outside`
+fn material_bit(name: &str) -> Result<usize, String> {
+    if name.eq_ignore_ascii_case("outside") {
+        return Ok(OUTSIDE);
+    }
+    PixelType::from_name(name)
+        .map(PixelType::index)
+        .ok_or_else(|| format!("Unknown material '{name}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::math::vec2;
+
+    const SYMBOLS: &str = "
+symbol S = Sand
+symbol ~ = Water
+symbol L = Grass
+symbol _ = Air
+";
+
+    fn rules(text: &str) -> Vec<Rule> {
+        parse(&format!("{SYMBOLS}{text}")).unwrap()
+    }
+
+    fn error(text: &str) -> String {
+        match parse(&format!("{SYMBOLS}{text}")) {
+            Ok(_) => panic!("expected an error for {text}"),
+            Err(error) => error,
+        }
+    }
+
+    fn rule_set(text: &str) -> RuleSet {
+        let mut rule_set = RuleSet::default();
+        for rule in rules(text) {
+            rule_set.add(rule);
+        }
+        rule_set
+    }
+
+    /// A neighbourhood written as three rows, with the symbols above and `#` for outside the world
+    fn neighbourhood(rows: [&str; 3]) -> [usize; 9] {
+        let cells: Vec<usize> = rows
+            .concat()
+            .chars()
+            .map(|cell| match cell {
+                'S' => PixelType::Sand.index(),
+                '~' => PixelType::Water.index(),
+                'L' => PixelType::Grass.index(),
+                '_' => PixelType::Air.index(),
+                _ => OUTSIDE,
+            })
+            .collect();
+        cells.try_into().unwrap()
+    }
+
+    #[test]
+    fn parses_the_example() {
+        let rules = rules(
+            "
+rule sand_sinks
+chance 0.5
+mirror
+***  ***
+*S*  *9*
+**~  **5
+
+rule birth
+neighbours L 3
+*** -> ***
+*_* -> *L*
+*** -> ***
+",
+        );
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].name, "sand_sinks");
+        assert_eq!(rules[0].chance, 0.5);
+        assert!(rules[0].mirror && !rules[0].rotate);
+        assert_eq!(rules[1].name, "birth");
+        assert_eq!(rules[1].result[MIDDLE], Write::Set(PixelType::Grass));
+    }
+
+    #[test]
+    fn digits_copy_cells_both_ways() {
+        let rules = rules(
+            "
+rule sand_sinks
+mirror
+***  ***
+*S*  *9*
+**~  **5
+",
+        );
+        let variants = rules[0].variants();
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].result[MIDDLE], Write::Copy(8));
+        assert_eq!(variants[0].result[8], Write::Copy(MIDDLE));
+        // The mirrored rule swaps with the other diagonal
+        assert_eq!(variants[1].result[MIDDLE], Write::Copy(6));
+        assert_eq!(variants[1].result[6], Write::Copy(MIDDLE));
+
+        assert!(variants[0].matches(&neighbourhood(["___", "_S_", "__~"])));
+        assert!(!variants[0].matches(&neighbourhood(["___", "_S_", "~__"])));
+        assert!(variants[1].matches(&neighbourhood(["___", "_S_", "~__"])));
+    }
+
+    #[test]
+    fn copying_a_cell_onto_itself_keeps_it() {
+        let rules = rules(
+            "
+rule still
+***  ***
+*S*  *5*
+***  ***
+",
+        );
+        assert_eq!(rules[0].variants()[0].result, [Write::Keep; 9]);
+    }
+
+    #[test]
+    fn written_cells_never_match_outside() {
+        let rules = rules(
+            "
+rule fill_below
+***  ***
+*S*  ***
+***  *S*
+",
+        );
+        let variant = &rules[0].variants()[0];
+        assert!(variant.matches(&neighbourhood(["###", "#S#", "#_#"])));
+        assert!(!variant.matches(&neighbourhood(["___", "_S_", "_#_"])));
+    }
+
+    #[test]
+    fn rotated_and_mirrored_variants_are_deduplicated() {
+        let count = |pattern: [&str; 3], options: &str| {
+            let rules = rules(&format!(
+                "rule test\n{options}\n{}  ***\n{}  ***\n{}  ***\n",
+                pattern[0], pattern[1], pattern[2]
+            ));
+            rules[0].variants().len()
+        };
+        // The same all the way round
+        assert_eq!(count(["***", "*S*", "***"], "rotate\nmirror"), 1);
+        // Turns to four sides, and mirroring adds nothing new
+        assert_eq!(count(["***", "*S*", "*~*"], "rotate\nmirror"), 4);
+        assert_eq!(count(["***", "*S*", "**~"], "rotate\nmirror"), 4);
+        // Every turn and flip is different
+        assert_eq!(count(["***", "*S*", "*~~"], "rotate\nmirror"), 8);
+        assert_eq!(count(["***", "*S*", "*~*"], "mirror"), 1);
+        assert_eq!(count(["***", "*S*", "**~"], "mirror"), 2);
+    }
+
+    #[test]
+    fn neighbours_count_the_cells_around_the_middle() {
+        let rules = rules(
+            "
+rule death
+neighbours L 0,1,4,5,6,7,8
+***  ***
+*L*  *_*
+***  ***
+",
+        );
+        let death = &rules[0].variants()[0];
+        assert!(death.matches(&neighbourhood(["___", "_L_", "___"])));
+        assert!(death.matches(&neighbourhood(["L__", "_L_", "___"])));
+        assert!(!death.matches(&neighbourhood(["LL_", "_L_", "___"])));
+        // Three around it makes four with the middle, which doesn't count
+        assert!(!death.matches(&neighbourhood(["LLL", "_L_", "___"])));
+        assert!(death.matches(&neighbourhood(["LLL", "LL_", "___"])));
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        let rows = "***  ***\n*S*  *_*\n***  ***\n";
+        assert!(error("rule a\n***  ***\n*S*  *_*\nrule b\n").contains("has 2 rows"));
+        assert!(error(&format!("rule a\n{rows}***  ***\n")).contains("already has its 3 rows"));
+        assert!(error("rule a\n***  ***\nchance 0.5\n").contains("before the rows"));
+        assert!(error(&format!("rule a\n{rows}mirror\n")).contains("before the rows"));
+        assert!(error("rule a\n***  ***\n").contains("needs 3 rows"));
+        assert!(error(rows).contains("expected a symbol or a rule"));
+        assert!(error("rule a\nchance 2\n").contains("not a chance"));
+        assert!(error("rule a\nneighbours L 9\n").contains("not a count"));
+        assert!(error("rule a\n***  ***\n*X*  ***\n***  ***\n").contains("Unknown symbol"));
+        assert!(error("rule a\n**  ***\n").contains("3 cells wide"));
+        assert!(
+            error("symbol A = Sand | Water\nrule a\n***  ***\n*S*  *A*\n***  ***\n")
+                .contains("single material")
+        );
+        assert!(error("symbol Q = Lava\n").contains("Unknown material"));
+    }
+
+    #[test]
+    fn patterns_see_across_chunk_borders() {
+        let _ = crate::CHUNK_SIZE.set((160, 90));
+        let mut chunk_grid = ChunkGrid::new(1, RandGenerator::new());
+        chunk_grid.set_rules(rule_set(
+            "
+rule birth
+neighbours L 3
+***  ***
+*_*  *L*
+***  ***
+
+rule death
+neighbours L 0,1,4,5,6,7,8
+***  ***
+*L*  *_*
+***  ***
+",
+        ));
+        // A blinker lying across the border between the two top chunks
+        let row = [(159, 40), (160, 40), (161, 40)];
+        let column = [(160, 39), (160, 40), (160, 41)];
+        for (x, y) in row {
+            chunk_grid.set_pixel(vec2(x as f32, y as f32), PixelType::Grass);
+        }
+        let grass = |chunk_grid: &ChunkGrid| {
+            let mut cells = vec![];
+            for y in 35..46 {
+                for x in 155..166 {
+                    if chunk_grid.get_pixel(vec2(x as f32, y as f32)) == Some(PixelType::Grass) {
+                        cells.push((x, y));
+                    }
+                }
+            }
+            cells
+        };
+        chunk_grid.update();
+        assert_eq!(grass(&chunk_grid), column);
+        chunk_grid.update();
+        assert_eq!(grass(&chunk_grid), row);
+    }
+
+    #[test]
+    fn rules_never_write_outside_the_world() {
+        let _ = crate::CHUNK_SIZE.set((160, 90));
+        let mut chunk_grid = ChunkGrid::new(1, RandGenerator::new());
+        chunk_grid.set_rules(rule_set(
+            "
+rule grow_left
+***  ***
+*L*  L**
+***  ***
+",
+        ));
+        chunk_grid.set_pixel(vec2(0.0, 10.0), PixelType::Grass);
+        chunk_grid.update();
+        assert_eq!(chunk_grid.material_counts()[PixelType::Grass.index()], 1);
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, fs, rc::Rc};
+
+use crate::{
+    chunk_size,
+    history::Snapshot,
+    pixel::PixelType,
+    pixel_grid::ChunkGrid,
+    rigid_body::{BodyState, RigidBody},
+};
+
+/// A chunk stored as runs of the same pixel type, since most of a chunk is usually Air
+#[derive(Serialize, Deserialize)]
+struct SavedChunk {
+    key: (i32, i32),
+    runs: Vec<(PixelType, u32)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedWorld {
+    seed: u64,
+    tick: u64,
+    chunks: Vec<SavedChunk>,
+    // Saves from before rigid bodies have none
+    #[serde(default)]
+    bodies: Vec<BodyState>,
+}
+
+/// Writes the whole grid, including its seed, tick and rigid bodies, to `path`
+pub fn save_world(chunk_grid: &ChunkGrid, path: &str) -> Result<(), Box<dyn Error>> {
+    let snapshot = chunk_grid.snapshot();
+    let mut chunks = vec![];
+    for (key, data) in snapshot.chunks() {
+        let mut runs: Vec<(PixelType, u32)> = vec![];
+        for pixel_type in data.iter() {
+            match runs.last_mut() {
+                Some((last, count)) if last == pixel_type => *count += 1,
+                _ => runs.push((*pixel_type, 1)),
+            }
+        }
+        chunks.push(SavedChunk { key: *key, runs });
+    }
+    let world = SavedWorld {
+        seed: chunk_grid.seed(),
+        tick: snapshot.tick(),
+        chunks,
+        bodies: snapshot.bodies().iter().map(RigidBody::state).collect(),
+    };
+    let text = ron::ser::to_string_pretty(&world, ron::ser::PrettyConfig::default())?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+/// Replaces the grid with the world saved at `path`
+pub fn load_world(chunk_grid: &mut ChunkGrid, path: &str) -> Result<(), Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let world: SavedWorld = ron::from_str(&text)?;
+
+    let pixels_per_chunk = chunk_size().0 * chunk_size().1;
+    let mut chunks = BTreeMap::new();
+    for chunk in world.chunks {
+        let mut data = Vec::with_capacity(pixels_per_chunk);
+        for (pixel_type, count) in chunk.runs {
+            data.extend(std::iter::repeat_n(pixel_type, count as usize));
+        }
+        if data.len() != pixels_per_chunk {
+            return Err(format!(
+                "Chunk {:?} has {} pixels, expected {pixels_per_chunk}",
+                chunk.key,
+                data.len(),
+            )
+            .into());
+        }
+        chunks.insert(chunk.key, Rc::new(data));
+    }
+
+    let bodies = world
+        .bodies
+        .into_iter()
+        .map(RigidBody::from_state)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    chunk_grid.restart(world.seed);
+    chunk_grid.restore(&Snapshot::new(world.tick, chunks, bodies));
+    Ok(())
+}
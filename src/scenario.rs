@@ -0,0 +1,487 @@
+//! Scenario files: an initial world, actions at set ticks and checks on the world, in RON.
+//!
+//! ```ron
+//! Scenario(
+//!     seed: 1,
+//!     setup: [
+//!         Prefab(name: "funnel", at: (40, 10)),
+//!         Fill(from: (0, 150), to: (319, 159), material: Stone),
+//!     ],
+//!     actions: [
+//!         (tick: 50, action: Paint(from: (60, 5), to: (80, 5), material: Sand, brush: Circle, size: 3)),
+//!         // Pours water every tick from 100 until 200
+//!         (tick: 100, until: Some(200), action: Paint(from: (20, 5), to: (20, 5), material: Water, brush: Spray, size: 4)),
+//!     ],
+//!     assertions: [
+//!         (tick: 600, check: Below(material: Water, y: 80, at_least: 0.9)),
+//!         (tick: 600, check: Count(material: Sand, min: Some(100))),
+//!     ],
+//! )
+//! ```
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs};
+
+use crate::{
+    brush::{Brush, BrushType},
+    config_path,
+    image_io::{Palette, import_png},
+    pixel::PixelType,
+    pixel_grid::{ChunkGrid, to_world_position},
+    prefab::read_pattern,
+};
+
+/// Something a scenario does to the world. Positions are in world pixels
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ScenarioAction {
+    /// Fills the rectangle between two corners, both included
+    Fill {
+        from: (i32, i32),
+        to: (i32, i32),
+        material: PixelType,
+    },
+    /// A brush stroke, like dragging the mouse from `from` to `to`
+    Paint {
+        from: (i32, i32),
+        to: (i32, i32),
+        material: PixelType,
+        brush: BrushType,
+        size: f32,
+    },
+    /// Places a prefab from the library with its top left corner at `at`
+    Prefab { name: String, at: (i32, i32) },
+    /// Turns a PNG into materials with the palette, and places it with its top left corner at `at`
+    Image { path: String, at: (i32, i32) },
+}
+
+impl ScenarioAction {
+    fn apply(&self, chunk_grid: &mut ChunkGrid) -> Result<(), Box<dyn Error>> {
+        let point = |(x, y): (i32, i32)| vec2(x as f32, y as f32);
+        match self {
+            ScenarioAction::Fill { from, to, material } => {
+                Brush::new(*material, BrushType::Rectangle, 1.0, 0.0).stroke(
+                    point(*from),
+                    point(*to),
+                    chunk_grid,
+                );
+            }
+            ScenarioAction::Paint {
+                from,
+                to,
+                material,
+                brush,
+                size,
+            } => {
+                Brush::new(*material, *brush, *size, 0.1).stroke(
+                    point(*from),
+                    point(*to),
+                    chunk_grid,
+                );
+            }
+            ScenarioAction::Prefab { name, at } => {
+                let path = config_path("prefabs").join(format!("{name}.ron"));
+                read_pattern(&path)
+                    .map_err(|error| format!("Failed to load prefab {name}: {error}"))?
+                    .place(point(*at), chunk_grid);
+            }
+            ScenarioAction::Image { path, at } => {
+                let palette = Palette::load_or_create(&config_path("palette.ron"));
+                import_png(path, &palette)
+                    .map_err(|error| format!("Failed to import {path}: {error}"))?
+                    .place(point(*at), chunk_grid);
+            }
+        }
+        chunk_grid.update_texture();
+        Ok(())
+    }
+}
+
+/// An action that happens at `tick`, and again every tick until `until` if it is set
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimedAction {
+    pub tick: u64,
+    #[serde(default)]
+    pub until: Option<u64>,
+    pub action: ScenarioAction,
+}
+
+/// Something that should be true about the world
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Check {
+    /// How many pixels of a material there are
+    Count {
+        material: PixelType,
+        #[serde(default)]
+        min: Option<usize>,
+        #[serde(default)]
+        max: Option<usize>,
+    },
+    /// The share of a material's pixels that are below the line `y`
+    Below {
+        material: PixelType,
+        y: i32,
+        at_least: f32,
+    },
+    /// The share of the rectangle between two corners that is filled with a material
+    Covers {
+        from: (i32, i32),
+        to: (i32, i32),
+        material: PixelType,
+        at_least: f32,
+    },
+    /// At most this many pixels moved last tick
+    Settled { max_moving: usize },
+}
+
+impl Check {
+    /// Whether the check holds, and what it checks with the value it found
+    fn evaluate(&self, chunk_grid: &ChunkGrid) -> (bool, String) {
+        match self {
+            Check::Count { material, min, max } => {
+                let count = chunk_grid.material_counts()[material.index()];
+                let passed =
+                    min.is_none_or(|min| count >= min) && max.is_none_or(|max| count <= max);
+                let bounds = match (min, max) {
+                    (Some(min), Some(max)) => format!("between {min} and {max}"),
+                    (Some(min), None) => format!("at least {min}"),
+                    (None, Some(max)) => format!("at most {max}"),
+                    (None, None) => String::from("any number of"),
+                };
+                (
+                    passed,
+                    format!("{bounds} {} pixels (found {count})", material.get()),
+                )
+            }
+            Check::Below {
+                material,
+                y,
+                at_least,
+            } => {
+                let (mut total, mut below) = (0, 0);
+                for (key, chunk) in chunk_grid.chunks() {
+                    for cell_y in 0..chunk.height() {
+                        for cell_x in 0..chunk.width() {
+                            if chunk.get(cell_x, cell_y) == Some(material) {
+                                total += 1;
+                                if to_world_position(*key, (cell_x, cell_y)).1 > *y {
+                                    below += 1;
+                                }
+                            }
+                        }
+                    }
+                }
+                let share = fraction(below, total);
+                (
+                    share >= *at_least,
+                    format!(
+                        "at least {:.0}% of {} below y={y} (found {:.1}%)",
+                        at_least * 100.0,
+                        material.get(),
+                        share * 100.0
+                    ),
+                )
+            }
+            Check::Covers {
+                from,
+                to,
+                material,
+                at_least,
+            } => {
+                let (mut total, mut covered) = (0, 0);
+                for y in from.1.min(to.1)..=from.1.max(to.1) {
+                    for x in from.0.min(to.0)..=from.0.max(to.0) {
+                        total += 1;
+                        if chunk_grid.get_pixel(vec2(x as f32, y as f32)) == Some(*material) {
+                            covered += 1;
+                        }
+                    }
+                }
+                let share = fraction(covered, total);
+                (
+                    share >= *at_least,
+                    format!(
+                        "at least {:.0}% of {from:?} to {to:?} is {} (found {:.1}%)",
+                        at_least * 100.0,
+                        material.get(),
+                        share * 100.0
+                    ),
+                )
+            }
+            Check::Settled { max_moving } => {
+                let moving = chunk_grid.moving_pixels();
+                (
+                    moving <= *max_moving,
+                    format!("at most {max_moving} pixels moving (found {moving})"),
+                )
+            }
+        }
+    }
+}
+
+fn fraction(part: usize, total: usize) -> f32 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f32 / total as f32
+    }
+}
+
+/// A check that is made once the world reaches `tick`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Assertion {
+    pub tick: u64,
+    pub check: Check,
+}
+
+/// How an assertion turned out
+pub struct Outcome {
+    pub tick: u64,
+    pub passed: bool,
+    pub description: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    seed: u64,
+    #[serde(default)]
+    setup: Vec<ScenarioAction>,
+    #[serde(default)]
+    actions: Vec<TimedAction>,
+    #[serde(default)]
+    assertions: Vec<Assertion>,
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The tick of the last action or assertion, the scenario is done after it
+    pub fn length(&self) -> u64 {
+        let actions = self
+            .actions
+            .iter()
+            .map(|action| action.until.unwrap_or(action.tick).max(action.tick));
+        let assertions = self.assertions.iter().map(|assertion| assertion.tick);
+        actions.chain(assertions).max().unwrap_or(0)
+    }
+}
+
+/// A scenario being played on a grid. Call `update` after every tick
+pub struct ScenarioRun {
+    scenario: Scenario,
+    outcomes: Vec<Outcome>,
+    // The last tick that was handled, so nothing happens twice
+    last_tick: Option<u64>,
+}
+
+impl ScenarioRun {
+    /// Builds the initial world on a grid that was just restarted with the scenario's seed
+    pub fn start(scenario: Scenario, chunk_grid: &mut ChunkGrid) -> Result<Self, Box<dyn Error>> {
+        for action in &scenario.setup {
+            action.apply(chunk_grid)?;
+        }
+        let mut run = Self {
+            scenario,
+            outcomes: vec![],
+            last_tick: None,
+        };
+        run.update(chunk_grid);
+        Ok(run)
+    }
+
+    /// Makes the assertions and then does the actions of the tick the grid is at
+    pub fn update(&mut self, chunk_grid: &mut ChunkGrid) {
+        let tick = chunk_grid.tick();
+        if self.is_finished() || self.last_tick.is_some_and(|last| tick <= last) {
+            return;
+        }
+        self.last_tick = Some(tick);
+
+        for assertion in self
+            .scenario
+            .assertions
+            .iter()
+            .filter(|assertion| assertion.tick == tick)
+        {
+            let (passed, description) = assertion.check.evaluate(chunk_grid);
+            println!(
+                "Scenario tick {tick}: {} {description}",
+                if passed { "passed" } else { "FAILED" }
+            );
+            self.outcomes.push(Outcome {
+                tick,
+                passed,
+                description,
+            });
+        }
+        for timed in &self.scenario.actions {
+            if tick < timed.tick || tick > timed.until.unwrap_or(timed.tick) {
+                continue;
+            }
+            // An action that can't be done fails the scenario, like an assertion would
+            if let Err(error) = timed.action.apply(chunk_grid) {
+                println!("Scenario tick {tick}: FAILED {error}");
+                self.outcomes.push(Outcome {
+                    tick,
+                    passed: false,
+                    description: error.to_string(),
+                });
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.last_tick
+            .is_some_and(|tick| tick >= self.scenario.length())
+    }
+
+    /// The tick the scenario is at and the tick it ends at
+    pub fn progress(&self) -> (u64, u64) {
+        (self.last_tick.unwrap_or(0), self.scenario.length())
+    }
+
+    pub fn outcomes(&self) -> &[Outcome] {
+        &self.outcomes
+    }
+
+    pub fn failures(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| !outcome.passed)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::rand::RandGenerator;
+
+    fn grid() -> ChunkGrid {
+        let _ = crate::CHUNK_SIZE.set((160, 90));
+        ChunkGrid::new(1, RandGenerator::new())
+    }
+
+    fn fill(chunk_grid: &mut ChunkGrid, from: (i32, i32), to: (i32, i32), material: PixelType) {
+        ScenarioAction::Fill { from, to, material }
+            .apply(chunk_grid)
+            .unwrap();
+    }
+
+    fn passes(check: Check, chunk_grid: &ChunkGrid) -> bool {
+        check.evaluate(chunk_grid).0
+    }
+
+    #[test]
+    fn counts_materials_against_their_bounds() {
+        let mut chunk_grid = grid();
+        fill(&mut chunk_grid, (0, 0), (9, 4), PixelType::Sand);
+        let count = |min, max| Check::Count {
+            material: PixelType::Sand,
+            min,
+            max,
+        };
+        assert!(passes(count(Some(50), Some(50)), &chunk_grid));
+        assert!(passes(count(None, None), &chunk_grid));
+        assert!(!passes(count(Some(51), None), &chunk_grid));
+        assert!(!passes(count(None, Some(49)), &chunk_grid));
+    }
+
+    #[test]
+    fn measures_the_share_below_a_line_across_chunks() {
+        let mut chunk_grid = grid();
+        // Ten rows, six of them in the chunks below the first row of chunks
+        fill(&mut chunk_grid, (100, 84), (199, 93), PixelType::Water);
+        let below = |y, at_least| Check::Below {
+            material: PixelType::Water,
+            y,
+            at_least,
+        };
+        assert!(passes(below(87, 0.6), &chunk_grid));
+        assert!(!passes(below(87, 0.61), &chunk_grid));
+        // Without any sand none of it is below the line
+        assert!(!passes(
+            Check::Below {
+                material: PixelType::Sand,
+                y: 0,
+                at_least: 0.01,
+            },
+            &chunk_grid
+        ));
+    }
+
+    #[test]
+    fn measures_how_much_of_a_rectangle_is_covered() {
+        let mut chunk_grid = grid();
+        fill(&mut chunk_grid, (0, 0), (9, 4), PixelType::Stone);
+        let covers = |to, at_least| Check::Covers {
+            from: (0, 0),
+            to,
+            material: PixelType::Stone,
+            at_least,
+        };
+        assert!(passes(covers((9, 4), 1.0), &chunk_grid));
+        assert!(passes(covers((9, 9), 0.5), &chunk_grid));
+        assert!(!passes(covers((9, 9), 0.51), &chunk_grid));
+    }
+
+    #[test]
+    fn an_untouched_world_has_settled() {
+        let chunk_grid = grid();
+        assert!(passes(Check::Settled { max_moving: 0 }, &chunk_grid));
+    }
+
+    #[test]
+    fn lasts_until_the_last_action_or_assertion() {
+        let scenario: Scenario = ron::from_str(
+            "Scenario(
+                actions: [
+                    (tick: 5, until: Some(300), action: Fill(from: (0, 0), to: (1, 1), material: Sand)),
+                    (tick: 400, action: Fill(from: (0, 0), to: (1, 1), material: Sand)),
+                ],
+                assertions: [(tick: 250, check: Settled(max_moving: 0))],
+            )",
+        )
+        .unwrap();
+        assert_eq!(scenario.seed(), 0);
+        assert_eq!(scenario.length(), 400);
+        assert_eq!(ron::from_str::<Scenario>("Scenario()").unwrap().length(), 0);
+    }
+
+    #[test]
+    fn makes_the_first_assertions_when_it_starts() {
+        let mut chunk_grid = grid();
+        let scenario: Scenario = ron::from_str(
+            "Scenario(
+                setup: [Fill(from: (0, 0), to: (9, 4), material: Sand)],
+                assertions: [
+                    (tick: 0, check: Count(material: Sand, min: Some(50))),
+                    (tick: 0, check: Count(material: Water, min: Some(1))),
+                    (tick: 10, check: Count(material: Sand, min: Some(50))),
+                ],
+            )",
+        )
+        .unwrap();
+        let run = ScenarioRun::start(scenario, &mut chunk_grid).unwrap();
+        assert_eq!(run.outcomes().len(), 2);
+        assert_eq!(run.failures(), 1);
+        assert_eq!(run.progress(), (0, 10));
+        assert!(!run.is_finished());
+    }
+
+    #[test]
+    fn setup_that_cant_be_done_stops_the_start() {
+        let mut chunk_grid = grid();
+        let scenario: Scenario =
+            ron::from_str(r#"Scenario(setup: [Prefab(name: "no such prefab", at: (0, 0))])"#)
+                .unwrap();
+        assert!(ScenarioRun::start(scenario, &mut chunk_grid).is_err());
+    }
+}
@@ -0,0 +1,426 @@
+//! Material behaviours written in Rhai, so they can be changed without recompiling.
+//!
+//! Every `<material>.rhai` file in the scripts folder replaces the update of that material,
+//! like `dirt.rhai` for Dirt. A script defines `fn update(cell)`, and returns what the cell should do:
+//!
+//! ```rhai
+//! fn update(cell) {
+//!     if cell.is_free(0, 1) && cell.random(0, 4) == 0 {
+//!         return move_to(0, 1);
+//!     }
+//!     if cell.get(0, -1) == "Water" {
+//!         return turn_into("Grass");
+//!     }
+//! }
+//! ```
+//!
+//! `cell` can only see the cells around it: `get(dx, dy)` (the material name, "" outside the chunk),
+//! `is_free(dx, dy)`, `random(low, high)` from the tick RNG, and its world position as `x` and `y`.
+//! A script returns nothing to stay put, `move_to(dx, dy)` to move into a free cell, `turn_into(name)`
+//! to change material, or `move_to(dx, dy).turn_into(name)` for both.
+//!
+//! A file named after a material that doesn't exist yet, like `lava.rhai`, adds that material.
+//! It shows up in the palette and can be painted like any other. Its script also says what it
+//! looks like, with a color and optionally one of the palette categories (Special if left out):
+//!
+//! ```rhai
+//! fn material() {
+//!     #{ color: "#ff6a00", category: "liquid" }
+//! }
+//! ```
+use macroquad::color::Color;
+use macroquad::rand::RandGenerator;
+use rhai::{AST, CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope};
+use std::{cell::Cell, error::Error, fs, path::Path};
+
+use crate::{
+    pixel::{Category, PixelType},
+    pixel_grid::{Chunk, GridMovement, NEIGHBOURHOOD_REACH, to_world_position},
+};
+
+const REACH: i32 = NEIGHBOURHOOD_REACH;
+const SIDE: usize = (REACH * 2 + 1) as usize;
+// How much work one update may do, so a script with an endless loop can't hang the simulation
+const MAX_OPERATIONS: u64 = 10_000;
+
+/// What a script sees of the world: the cells around the one it updates, and random numbers
+#[derive(Clone)]
+pub struct Neighbourhood {
+    // None for cells outside the chunk, those are for the grid to sort out
+    cells: [Option<PixelType>; SIDE * SIDE],
+    x: i64,
+    y: i64,
+    // Seeded from the tick RNG, so scripts stay deterministic in replays
+    random_state: u64,
+}
+
+impl Neighbourhood {
+    fn new(chunk: &Chunk, x: i32, y: i32, rng: &RandGenerator) -> Self {
+        let mut cells = [None; SIDE * SIDE];
+        for (cell, pixel_type) in cells.iter_mut().zip(chunk.neighbourhood(x, y, REACH)) {
+            *cell = pixel_type;
+        }
+        let (world_x, world_y) = to_world_position(chunk.key(), (x, y));
+        Self {
+            cells,
+            x: world_x as i64,
+            y: world_y as i64,
+            random_state: rng.gen_range(0, i32::MAX) as u64,
+        }
+    }
+
+    fn index(dx: i32, dy: i32) -> usize {
+        ((dy + REACH) as usize) * SIDE + (dx + REACH) as usize
+    }
+
+    fn in_reach(dx: i64, dy: i64) -> bool {
+        dx.abs() <= REACH as i64 && dy.abs() <= REACH as i64
+    }
+
+    fn cell(&self, dx: i64, dy: i64) -> Option<PixelType> {
+        if !Self::in_reach(dx, dy) {
+            return None;
+        }
+        self.cells[Self::index(dx as i32, dy as i32)]
+    }
+
+    fn get(&mut self, dx: i64, dy: i64) -> String {
+        self.cell(dx, dy)
+            .map_or(String::new(), |pixel_type| pixel_type.get().to_string())
+    }
+
+    fn is_free(&mut self, dx: i64, dy: i64) -> bool {
+        if !Self::in_reach(dx, dy) {
+            return false;
+        }
+        // Cells outside the chunk count as free, like in Chunk::query
+        self.cell(dx, dy)
+            .is_none_or(|pixel_type| pixel_type == PixelType::Air)
+    }
+
+    /// A random number in `low..high`
+    fn random(&mut self, low: i64, high: i64) -> i64 {
+        if high <= low {
+            return low;
+        }
+        // SplitMix64
+        self.random_state = self.random_state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        low + (z % (high - low) as u64) as i64
+    }
+}
+
+/// What a script wants its cell to do
+#[derive(Clone)]
+pub struct Proposal {
+    dx: i64,
+    dy: i64,
+    turn_into: Option<PixelType>,
+}
+
+fn material(name: &str) -> Result<PixelType, Box<EvalAltResult>> {
+    PixelType::from_name(name).ok_or_else(|| format!("Unknown material '{name}'").into())
+}
+
+/// A loaded script and the material it updates
+struct Script {
+    pixel_type: PixelType,
+    ast: AST,
+    // Set after the first error, so a broken script doesn't flood the output every tick
+    failed: Cell<bool>,
+}
+
+/// The material scripts from the scripts folder, and the sandboxed engine that runs them
+pub struct MaterialScripts {
+    engine: Engine,
+    scripts: Vec<Script>,
+}
+
+impl Default for MaterialScripts {
+    fn default() -> Self {
+        Self {
+            engine: sandboxed_engine(),
+            scripts: vec![],
+        }
+    }
+}
+
+impl MaterialScripts {
+    /// Compiles every script in `folder`. Scripts that don't compile are reported and skipped
+    pub fn load(folder: &Path) -> Self {
+        let mut scripts = Self::default();
+        let Ok(entries) = fs::read_dir(folder) else {
+            // No scripts folder means every material uses its built-in update
+            return scripts;
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "rhai")
+            })
+            .collect();
+        paths.sort();
+        for path in paths {
+            match scripts.compile(&path) {
+                Ok(script) => {
+                    println!(
+                        "Loaded script for {} from {}",
+                        script.pixel_type.get(),
+                        path.display()
+                    );
+                    scripts.scripts.push(script);
+                }
+                Err(error) => println!("Failed to load script {}: {error}", path.display()),
+            }
+        }
+        scripts
+    }
+
+    fn compile(&self, path: &Path) -> Result<Script, Box<dyn Error>> {
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        self.compile_source(name, &fs::read_to_string(path)?)
+    }
+
+    fn compile_source(&self, name: &str, source: &str) -> Result<Script, Box<dyn Error>> {
+        let ast = self.engine.compile(source)?;
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == "update" && function.params.len() == 1)
+        {
+            return Err("the script has no fn update(cell)".into());
+        }
+        let built_in = PixelType::BUILT_IN
+            .into_iter()
+            .find(|pixel_type| pixel_type.get().eq_ignore_ascii_case(name));
+        let pixel_type = match built_in {
+            Some(pixel_type) => pixel_type,
+            None => {
+                let (color, category) = self.describe(&ast)?;
+                PixelType::define(name, color, category)?
+            }
+        };
+        Ok(Script {
+            pixel_type,
+            ast,
+            failed: Cell::new(false),
+        })
+    }
+
+    /// Runs `fn material()` of a script that adds a material, for its color and category
+    fn describe(&self, ast: &AST) -> Result<(Color, Category), Box<dyn Error>> {
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == "material" && function.params.is_empty())
+        {
+            return Err("a new material needs a fn material() with its color".into());
+        }
+        let description = self.engine.call_fn_with_options::<Map>(
+            CallFnOptions::new().eval_ast(false),
+            &mut Scope::new(),
+            ast,
+            "material",
+            (),
+        )?;
+        let text = |key: &str| {
+            description
+                .get(key)
+                .map(|value| value.clone().into_string())
+                .transpose()
+                .map_err(|_| format!("the {key} of the material is not a string"))
+        };
+        let color = text("color")?.ok_or("the material has no color")?;
+        let category = match text("category")? {
+            Some(name) => {
+                Category::from_name(&name).ok_or_else(|| format!("unknown category '{name}'"))?
+            }
+            None => Category::Special,
+        };
+        Ok((parse_color(&color)?, category))
+    }
+
+    /// The materials that have a script
+    pub fn materials(&self) -> impl Iterator<Item = PixelType> + '_ {
+        self.scripts.iter().map(|script| script.pixel_type)
+    }
+
+    /// Runs the script of `pixel_type` for the cell at `x`, `y`.
+    /// Returns None when the material has no script, and the built-in update should be used
+    pub fn update(
+        &self,
+        pixel_type: PixelType,
+        chunk: &Chunk,
+        x: i32,
+        y: i32,
+        rng: &RandGenerator,
+    ) -> Option<Option<GridMovement>> {
+        let script = self
+            .scripts
+            .iter()
+            .find(|script| script.pixel_type == pixel_type)?;
+        if script.failed.get() {
+            return Some(None);
+        }
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            CallFnOptions::new().eval_ast(false),
+            &mut Scope::new(),
+            &script.ast,
+            "update",
+            (Neighbourhood::new(chunk, x, y, rng),),
+        );
+        let proposal = match result {
+            Ok(result) => result.try_cast::<Proposal>(),
+            Err(error) => {
+                println!("The {} script stopped working: {error}", pixel_type.get());
+                script.failed.set(true);
+                None
+            }
+        };
+        Some(proposal.and_then(|proposal| {
+            GridMovement::proposed(
+                chunk,
+                (x, y),
+                (proposal.dx as i32, proposal.dy as i32),
+                pixel_type,
+                proposal.turn_into.unwrap_or(pixel_type),
+            )
+        }))
+    }
+}
+
+/// Reads a color written as "#rrggbb"
+fn parse_color(text: &str) -> Result<Color, String> {
+    let error = || format!("'{text}' is not a color like \"#ff6a00\"");
+    let hex = text.strip_prefix('#').ok_or_else(error)?;
+    if hex.len() != 6 || !hex.chars().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(error());
+    }
+    let rgb = u32::from_str_radix(hex, 16).map_err(|_| error())?;
+    let [_, r, g, b] = rgb.to_be_bytes();
+    Ok(Color::from_rgba(r, g, b, 255))
+}
+
+/// An engine that only knows the neighbourhood API, with limits on how much a script can do
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(16)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(256)
+        .set_max_array_size(256)
+        .set_max_map_size(64);
+    // Scripts can't load other files
+    engine.disable_symbol("import");
+
+    engine
+        .register_type_with_name::<Neighbourhood>("Cell")
+        .register_fn("get", Neighbourhood::get)
+        .register_fn("is_free", Neighbourhood::is_free)
+        .register_fn("random", Neighbourhood::random)
+        .register_get("x", |cell: &mut Neighbourhood| cell.x)
+        .register_get("y", |cell: &mut Neighbourhood| cell.y);
+
+    engine
+        .register_type_with_name::<Proposal>("Proposal")
+        .register_fn(
+            "move_to",
+            |dx: i64, dy: i64| -> Result<Proposal, Box<EvalAltResult>> {
+                if !Neighbourhood::in_reach(dx, dy) {
+                    return Err(format!("({dx}, {dy}) is further than {REACH} cells away").into());
+                }
+                Ok(Proposal {
+                    dx,
+                    dy,
+                    turn_into: None,
+                })
+            },
+        )
+        .register_fn(
+            "turn_into",
+            |name: &str| -> Result<Proposal, Box<EvalAltResult>> {
+                Ok(Proposal {
+                    dx: 0,
+                    dy: 0,
+                    turn_into: Some(material(name)?),
+                })
+            },
+        )
+        .register_fn(
+            "turn_into",
+            |proposal: &mut Proposal, name: &str| -> Result<Proposal, Box<EvalAltResult>> {
+                Ok(Proposal {
+                    turn_into: Some(material(name)?),
+                    ..proposal.clone()
+                })
+            },
+        );
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(name: &str, source: &str) -> Result<PixelType, String> {
+        MaterialScripts::default()
+            .compile_source(name, source)
+            .map(|script| script.pixel_type)
+            .map_err(|error| error.to_string())
+    }
+
+    const UPDATE: &str = "fn update(cell) {}";
+
+    #[test]
+    fn scripts_named_after_a_material_replace_its_update() {
+        assert_eq!(compile("dirt", UPDATE), Ok(PixelType::Dirt));
+        assert_eq!(compile("Sand", UPDATE), Ok(PixelType::Sand));
+        assert!(compile("dirt", "fn tick(cell) {}").is_err());
+    }
+
+    #[test]
+    fn other_scripts_add_a_material() {
+        let source =
+            format!("{UPDATE} fn material() {{ #{{ color: \"#ff6a00\", category: \"Liquid\" }} }}");
+        let lava = compile("lava", &source).unwrap();
+        assert_eq!(lava.get(), "lava");
+        assert_eq!(lava.category(), Category::Liquid);
+        assert_eq!(lava.to_color(), Color::from_rgba(255, 106, 0, 255));
+        assert!(PixelType::all().contains(&lava));
+
+        // Loading it again keeps the material, with the new look
+        let source = format!("{UPDATE} fn material() {{ #{{ color: \"#000000\" }} }}");
+        assert_eq!(compile("lava", &source), Ok(lava));
+        assert_eq!(lava.category(), Category::Special);
+        assert_eq!(lava.to_color(), Color::from_rgba(0, 0, 0, 255));
+    }
+
+    #[test]
+    fn new_materials_need_a_valid_look() {
+        let error = compile("goo", UPDATE).unwrap_err();
+        assert!(error.contains("fn material()"), "{error}");
+        let material = |body: &str| compile("goo", &format!("{UPDATE} fn material() {{ {body} }}"));
+        assert!(material("#{ category: \"liquid\" }").is_err());
+        assert!(material("#{ color: 5 }").is_err());
+        assert!(material("#{ color: \"#12345\" }").is_err());
+        assert!(material("#{ color: \"#123456\", category: \"lava\" }").is_err());
+        assert!(PixelType::from_name("goo").is_none());
+    }
+
+    #[test]
+    fn reads_hex_colors() {
+        assert_eq!(
+            parse_color("#0080ff"),
+            Ok(Color::from_rgba(0, 128, 255, 255))
+        );
+        for text in ["0080ff", "#0080f", "#0080fff", "#+080ff", "#00g0ff"] {
+            assert!(parse_color(text).is_err(), "{text}");
+        }
+    }
+}
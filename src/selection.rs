@@ -0,0 +1,258 @@
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{pixel::PixelType, pixel_grid::ChunkGrid};
+
+/// A rectangle of pixels lifted out of the world, which can be turned around and placed somewhere else
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "PatternData")]
+pub struct Pattern {
+    width: usize,
+    height: usize,
+    // Row by row, starting at the top left
+    pixels: Vec<PixelType>,
+}
+
+/// A pattern as it is written in a file, checked before it becomes a `Pattern`,
+/// since everything that uses a pattern expects the pixels to fill the whole rectangle
+#[derive(Deserialize)]
+struct PatternData {
+    width: usize,
+    height: usize,
+    pixels: Vec<PixelType>,
+}
+
+impl TryFrom<PatternData> for Pattern {
+    type Error = String;
+
+    fn try_from(data: PatternData) -> Result<Self, Self::Error> {
+        let PatternData {
+            width,
+            height,
+            pixels,
+        } = data;
+        // The thumbnails are images, which can't be any bigger than this
+        let max = u16::MAX as usize;
+        if width == 0 || height == 0 || width > max || height > max {
+            return Err(format!(
+                "a pattern of {width} by {height} pixels is not allowed, both sides must be between 1 and {max}"
+            ));
+        }
+        if pixels.len() != width * height {
+            return Err(format!(
+                "a pattern of {width} by {height} pixels needs {} pixels, but has {}",
+                width * height,
+                pixels.len()
+            ));
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+impl Pattern {
+    /// Creates a pattern from its pixels, row by row starting at the top left
+    pub fn new(width: usize, height: usize, pixels: Vec<PixelType>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "Pattern size doesn't match its pixels"
+        );
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Copies the pixels between two corners, both included. Positions outside of the loaded chunks become Air
+    pub fn capture(chunk_grid: &ChunkGrid, corner: Vec2, opposite_corner: Vec2) -> Self {
+        let min = corner.min(opposite_corner);
+        let max = corner.max(opposite_corner);
+        let width = (max.x - min.x) as usize + 1;
+        let height = (max.y - min.y) as usize + 1;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let position = min + vec2(x as f32, y as f32);
+                pixels.push(chunk_grid.get_pixel(position).unwrap_or(PixelType::Air));
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> PixelType {
+        self.pixels[y * self.width + x]
+    }
+
+    /// The top left corner to place the pattern at, so it ends up centred on `center`
+    pub fn top_left_at(&self, center: Vec2) -> Vec2 {
+        (center - vec2(self.width as f32, self.height as f32) / 2.0).floor()
+    }
+
+    /// Writes the pattern into the world. Pixels that land outside of the loaded chunks are dropped
+    pub fn place(&self, top_left: Vec2, chunk_grid: &mut ChunkGrid) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                chunk_grid.set_pixel(top_left + vec2(x as f32, y as f32), self.get(x, y));
+            }
+        }
+    }
+
+    /// Turns the pattern a quarter turn clockwise
+    pub fn rotate(&mut self) {
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        // The new rows are the old columns, read from the bottom up
+        for x in 0..self.width {
+            for y in (0..self.height).rev() {
+                pixels.push(self.get(x, y));
+            }
+        }
+        self.pixels = pixels;
+        std::mem::swap(&mut self.width, &mut self.height);
+    }
+
+    /// Mirrors the pattern left to right, or top to bottom when `vertical` is set
+    pub fn flip(&mut self, vertical: bool) {
+        if vertical {
+            let rows: Vec<&[PixelType]> = self.pixels.chunks(self.width).rev().collect();
+            self.pixels = rows.concat();
+        } else {
+            for row in self.pixels.chunks_mut(self.width) {
+                row.reverse();
+            }
+        }
+    }
+
+    /// Draws a see-through version of the pattern, to show where it would be placed
+    pub fn draw_ghost(&self, top_left: Vec2) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let color = self.get(x, y).to_color();
+                draw_rectangle(
+                    top_left.x + x as f32,
+                    top_left.y + y as f32,
+                    1.0,
+                    1.0,
+                    Color::new(color.r, color.g, color.b, 0.5),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a pattern from rows of S(and), W(ater) and .(air)
+    fn pattern(rows: &[&str]) -> Pattern {
+        let pixels = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|cell| match cell {
+                'S' => PixelType::Sand,
+                'W' => PixelType::Water,
+                _ => PixelType::Air,
+            })
+            .collect();
+        Pattern {
+            width: rows[0].len(),
+            height: rows.len(),
+            pixels,
+        }
+    }
+
+    fn rows(pattern: &Pattern) -> Vec<String> {
+        (0..pattern.height())
+            .map(|y| {
+                (0..pattern.width())
+                    .map(|x| match pattern.get(x, y) {
+                        PixelType::Sand => 'S',
+                        PixelType::Water => 'W',
+                        _ => '.',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn parse(text: &str) -> Result<Pattern, String> {
+        ron::from_str(text).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn reads_patterns_whose_pixels_fill_the_rectangle() {
+        let pattern = parse("(width: 2, height: 1, pixels: [Sand, Water])").unwrap();
+        assert_eq!(rows(&pattern), ["SW"]);
+    }
+
+    #[test]
+    fn rejects_patterns_with_the_wrong_number_of_pixels() {
+        let error = parse("(width: 2, height: 2, pixels: [Sand, Water, Air])").unwrap_err();
+        assert!(error.contains("needs 4 pixels, but has 3"), "{error}");
+        assert!(parse("(width: 1, height: 1, pixels: [Sand, Sand])").is_err());
+    }
+
+    #[test]
+    fn rejects_empty_and_oversized_patterns() {
+        for (width, height) in [(0, 0), (0, 3), (3, 0), (65536, 1), (1, usize::MAX)] {
+            let text = format!("(width: {width}, height: {height}, pixels: [])");
+            let error = parse(&text).unwrap_err();
+            assert!(error.contains("is not allowed"), "{error}");
+        }
+    }
+
+    #[test]
+    fn rotates_a_quarter_turn_clockwise() {
+        let mut pattern = pattern(&["SW.", "..S"]);
+        pattern.rotate();
+        assert_eq!((pattern.width(), pattern.height()), (2, 3));
+        assert_eq!(rows(&pattern), [".S", ".W", "S."]);
+    }
+
+    #[test]
+    fn four_rotations_are_no_rotation() {
+        let original = pattern(&["SW.", "..S"]);
+        let mut rotated = original.clone();
+        for _ in 0..4 {
+            rotated.rotate();
+        }
+        assert_eq!(rows(&rotated), rows(&original));
+    }
+
+    #[test]
+    fn flips_left_to_right_and_top_to_bottom() {
+        let mut pattern = pattern(&["SW.", "..S"]);
+        pattern.flip(false);
+        assert_eq!(rows(&pattern), [".WS", "S.."]);
+        pattern.flip(true);
+        assert_eq!(rows(&pattern), ["S..", ".WS"]);
+    }
+
+    #[test]
+    fn single_row_and_column_patterns() {
+        let mut row = pattern(&["SW"]);
+        row.flip(true);
+        assert_eq!(rows(&row), ["SW"]);
+        row.rotate();
+        assert_eq!(rows(&row), ["S", "W"]);
+        row.flip(false);
+        assert_eq!(rows(&row), ["S", "W"]);
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, path::Path};
+
+use crate::{brush::BrushType, camera::ScalingMode, pixel::PixelType};
+
+/// Everything a user can tune about their setup. Stored in the platform config directory,
+/// loaded at startup and written back when the app exits.
+/// Fields missing from the file fall back to their default, so old files keep working
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // These only take effect after a restart
+    pub window_width: u32,
+    pub window_height: u32,
+    pub chunk_size: (u32, u32),
+    pub snapshot_interval: u32,
+    pub snapshot_capacity: u32,
+    pub default_material: PixelType,
+    pub default_brush: BrushType,
+    pub default_brush_size: f32,
+    pub default_spray_density: f32,
+
+    // These are used as soon as they change
+    pub render_size: (u32, u32),
+    pub scaling_mode: ScalingMode,
+    pub favourite_materials: Vec<PixelType>,
+    pub scroll_up_threshold: f32,
+    pub scroll_down_threshold: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            window_width: 1280,
+            window_height: 720,
+            chunk_size: (160, 90),
+            // Take a snapshot every 10 ticks and keep the last 600 of them
+            snapshot_interval: 10,
+            snapshot_capacity: 600,
+            default_material: PixelType::Dirt,
+            default_brush: BrushType::Pixel,
+            default_brush_size: 5.0,
+            // Spray a tenth of the pixels under the brush every frame
+            default_spray_density: 0.1,
+
+            render_size: (240, 125),
+            scaling_mode: ScalingMode::Fill,
+
+            favourite_materials: vec![],
+            // Once we scrolled 120.0 up (idk in what unit) we count it as '1 scroll'
+            scroll_up_threshold: 120.0,
+            scroll_down_threshold: -110.0,
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings from `path`. If the file doesn't exist yet it is created with the defaults.
+    /// If it can't be read we fall back to the defaults. Values out of range are corrected
+    pub fn load_or_create(path: &Path) -> Self {
+        if !path.exists() {
+            let defaults = Self::default();
+            match defaults.save(path) {
+                Ok(()) => println!("Wrote default settings to {}", path.display()),
+                Err(error) => println!("Failed to write settings to {}: {error}", path.display()),
+            }
+            return defaults;
+        }
+        Self::load_or_default(path)
+    }
+
+    /// Loads the settings from `path` without ever writing to it, for runs that shouldn't touch
+    /// the config directory. A missing or unreadable file gives the defaults.
+    /// Values out of range are corrected
+    pub fn load_or_default(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::default();
+        }
+        match Self::load(path) {
+            Ok(mut settings) => {
+                for correction in settings.validate() {
+                    println!("Settings in {}: {correction}", path.display());
+                }
+                settings
+            }
+            Err(error) => {
+                println!(
+                    "Failed to load settings from {}: {error}, using the defaults",
+                    path.display()
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Clamps every value into the range the settings window allows, and returns what was changed.
+    /// A zero render size or scroll threshold would otherwise reach the renderer and the input
+    /// handling as is
+    pub fn validate(&mut self) -> Vec<String> {
+        let mut corrections = vec![];
+        // The thresholds are compared against the wheel movement with their sign,
+        // so a threshold pointing the wrong way is flipped first
+        if self.scroll_up_threshold < 0.0 || self.scroll_down_threshold > 0.0 {
+            corrections.push("scroll thresholds have the wrong sign, flipping them".to_string());
+            self.scroll_up_threshold = self.scroll_up_threshold.abs();
+            self.scroll_down_threshold = -self.scroll_down_threshold.abs();
+        }
+        let mut correct = |name: &str, value: &mut f32, min: f32, max: f32| {
+            let corrected = if value.is_finite() {
+                value.clamp(min, max)
+            } else {
+                min
+            };
+            if corrected != *value {
+                corrections.push(format!("{name} {value} is out of range, using {corrected}"));
+                *value = corrected;
+            }
+        };
+        correct(
+            "scroll_up_threshold",
+            &mut self.scroll_up_threshold,
+            1.0,
+            1000.0,
+        );
+        correct(
+            "scroll_down_threshold",
+            &mut self.scroll_down_threshold,
+            -1000.0,
+            -1.0,
+        );
+        correct(
+            "default_brush_size",
+            &mut self.default_brush_size,
+            1.0,
+            100.0,
+        );
+        correct(
+            "default_spray_density",
+            &mut self.default_spray_density,
+            0.01,
+            1.0,
+        );
+
+        let mut correct = |name: &str, value: &mut u32, min: u32, max: u32| {
+            let corrected = (*value).clamp(min, max);
+            if corrected != *value {
+                corrections.push(format!("{name} {value} is out of range, using {corrected}"));
+                *value = corrected;
+            }
+        };
+        correct("window_width", &mut self.window_width, 320, 7680);
+        correct("window_height", &mut self.window_height, 180, 4320);
+        correct("chunk_size width", &mut self.chunk_size.0, 8, 1024);
+        correct("chunk_size height", &mut self.chunk_size.1, 8, 1024);
+        correct("snapshot_interval", &mut self.snapshot_interval, 1, 1000);
+        correct("snapshot_capacity", &mut self.snapshot_capacity, 1, 10000);
+        correct("render_size width", &mut self.render_size.0, 16, 3840);
+        correct("render_size height", &mut self.render_size.1, 16, 2160);
+        corrections
+    }
+
+    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use std::{collections::VecDeque, error::Error, fs};
+
+use crate::{
+    pixel::{MATERIAL_COUNT, PixelType},
+    pixel_grid::ChunkGrid,
+};
+
+// How many ticks the charts go back
+const HISTORY: usize = 1000;
+
+/// The material counts of one tick
+#[derive(Clone, Copy)]
+pub struct Sample {
+    pub tick: u64,
+    pub counts: [usize; MATERIAL_COUNT],
+    pub moving: usize,
+}
+
+impl Sample {
+    /// Pixels that are not Air and didn't move last tick
+    pub fn settled(&self) -> usize {
+        let pixels: usize = self.counts.iter().sum::<usize>() - self.counts[PixelType::Air.index()];
+        pixels.saturating_sub(self.moving)
+    }
+}
+
+/// Keeps the material counts of the last ticks, for the stats charts
+#[derive(Default)]
+pub struct Stats {
+    history: VecDeque<Sample>,
+}
+
+impl Stats {
+    /// Samples the grid after a tick
+    pub fn record_tick(&mut self, chunk_grid: &ChunkGrid) {
+        // Going back in time (scrubbing, loading, restarting) makes the old samples meaningless
+        if self
+            .history
+            .back()
+            .is_some_and(|last| last.tick >= chunk_grid.tick())
+        {
+            self.history.clear();
+        }
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(Sample {
+            tick: chunk_grid.tick(),
+            counts: chunk_grid.material_counts(),
+            moving: chunk_grid.moving_pixels(),
+        });
+    }
+
+    pub fn history(&self) -> &VecDeque<Sample> {
+        &self.history
+    }
+
+    /// Writes the history to a CSV file, one row per tick
+    pub fn export_csv(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut csv = String::from("tick");
+        let materials = PixelType::all();
+        for pixel_type in &materials {
+            csv.push_str(&format!(",{}", pixel_type.get()));
+        }
+        csv.push_str(",moving,settled\n");
+        for sample in &self.history {
+            csv.push_str(&sample.tick.to_string());
+            for pixel_type in &materials {
+                csv.push_str(&format!(",{}", sample.counts[pixel_type.index()]));
+            }
+            csv.push_str(&format!(",{},{}\n", sample.moving, sample.settled()));
+        }
+        fs::write(path, csv)?;
+        Ok(())
+    }
+}